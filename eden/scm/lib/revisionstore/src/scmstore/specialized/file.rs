@@ -7,14 +7,14 @@
 
 // TODO(meyer): Remove this
 #![allow(dead_code)]
-use std::collections::{hash_map, HashMap, HashSet};
+use std::collections::{hash_map, HashMap, HashSet, VecDeque};
 use std::convert::{TryFrom, TryInto};
 use std::ops::{BitAnd, BitOr, Not, Sub};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{anyhow, bail, ensure, Error, Result};
-use parking_lot::RwLock;
+use parking_lot::{Condvar, Mutex, RwLock};
 use serde::{Deserialize, Serialize};
 
 use edenapi_types::FileEntry;
@@ -33,6 +33,852 @@ use crate::{
     ExtStoredPolicy, LocalStore, MemcacheStore, Metadata, StoreKey, StoreResult,
 };
 
+/// Which AEAD cipher is used to encrypt blobs at rest in `indexedlog_local`/`indexedlog_cache`
+/// and `lfs_local`/`lfs_cache`. The wire and hash formats are unaffected: the LFS sha256 pointer
+/// and `FileAuxData::content_sha256` always address the *plaintext* content.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EncryptionType {
+    AesGcm,
+    Chacha20Poly1305,
+}
+
+impl EncryptionType {
+    fn header_byte(self) -> u8 {
+        match self {
+            EncryptionType::AesGcm => 0,
+            EncryptionType::Chacha20Poly1305 => 1,
+        }
+    }
+
+    fn from_header_byte(b: u8) -> Result<Self> {
+        match b {
+            0 => Ok(EncryptionType::AesGcm),
+            1 => Ok(EncryptionType::Chacha20Poly1305),
+            _ => bail!("unknown at-rest encryption header byte: {}", b),
+        }
+    }
+}
+
+/// A 256-bit key plus the cipher it should be used with. Construct via `from_passphrase` to
+/// derive the key via Argon2id from a user passphrase and a per-store salt (persisted alongside
+/// the store so the same passphrase keeps deriving the same key across restarts), or via
+/// `from_raw_key` when the caller already manages key material itself.
+#[derive(Clone)]
+pub(crate) struct EncryptionConfig {
+    pub(crate) ty: EncryptionType,
+    pub(crate) key: [u8; 32],
+}
+
+const ENCRYPTION_NONCE_LEN: usize = 12;
+const ENCRYPTION_HEADER_LEN: usize = 1 + ENCRYPTION_NONCE_LEN;
+
+const ENCRYPTION_SALT_LEN: usize = 16;
+const ENCRYPTION_SALT_MAGIC: &[u8] = b"ENCSALT1";
+const ENCRYPTION_SALT_HEADER_FILE: &str = "encryption.salt";
+
+impl EncryptionConfig {
+    /// A config backed by a caller-supplied raw key, e.g. for tests or callers that manage their
+    /// own key material.
+    pub(crate) fn from_raw_key(ty: EncryptionType, key: [u8; 32]) -> Self {
+        EncryptionConfig { ty, key }
+    }
+
+    /// Derive the key via Argon2id from `passphrase` and a random salt, generated once and
+    /// persisted as `[ENCRYPTION_SALT_MAGIC][salt:16B]` in `ENCRYPTION_SALT_HEADER_FILE` under
+    /// `store_path` so re-opening the same store with the same passphrase derives the same key.
+    pub(crate) fn from_passphrase(
+        store_path: &Path,
+        ty: EncryptionType,
+        passphrase: &[u8],
+    ) -> Result<Self> {
+        let salt = load_or_create_encryption_salt(store_path)?;
+        let mut key = [0u8; 32];
+        argon2::Argon2::default()
+            .hash_password_into(passphrase, &salt, &mut key)
+            .map_err(|err| anyhow!("Argon2id key derivation failed: {}", err))?;
+        Ok(EncryptionConfig { ty, key })
+    }
+}
+
+/// Load this store's persisted salt header, creating it with a fresh random salt if this is the
+/// first time this store is being opened with at-rest encryption enabled.
+fn load_or_create_encryption_salt(store_path: &Path) -> Result<[u8; ENCRYPTION_SALT_LEN]> {
+    let header_path = store_path.join(ENCRYPTION_SALT_HEADER_FILE);
+    match std::fs::read(&header_path) {
+        Ok(data) => {
+            ensure!(
+                data.len() == ENCRYPTION_SALT_MAGIC.len() + ENCRYPTION_SALT_LEN
+                    && data.starts_with(ENCRYPTION_SALT_MAGIC),
+                "corrupt encryption salt header at {}",
+                header_path.display()
+            );
+            let mut salt = [0u8; ENCRYPTION_SALT_LEN];
+            salt.copy_from_slice(&data[ENCRYPTION_SALT_MAGIC.len()..]);
+            Ok(salt)
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            let mut salt = [0u8; ENCRYPTION_SALT_LEN];
+            rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut salt);
+            std::fs::create_dir_all(store_path)?;
+            let mut header = ENCRYPTION_SALT_MAGIC.to_vec();
+            header.extend_from_slice(&salt);
+            std::fs::write(&header_path, &header)?;
+            Ok(salt)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// AEAD-encrypt `plaintext`, returning `[type:u8][nonce:12B] || ciphertext`.
+fn encrypt_blob(config: &EncryptionConfig, plaintext: &[u8]) -> Result<Bytes> {
+    use aead::{Aead, NewAead};
+
+    let mut nonce_bytes = [0u8; ENCRYPTION_NONCE_LEN];
+    rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut nonce_bytes);
+    let nonce = aead::generic_array::GenericArray::from_slice(&nonce_bytes);
+
+    let ciphertext = match config.ty {
+        EncryptionType::AesGcm => {
+            let cipher = aes_gcm::Aes256Gcm::new(aead::generic_array::GenericArray::from_slice(
+                &config.key,
+            ));
+            cipher
+                .encrypt(nonce, plaintext)
+                .map_err(|_| anyhow!("AES-GCM encryption failed"))?
+        }
+        EncryptionType::Chacha20Poly1305 => {
+            let cipher = chacha20poly1305::ChaCha20Poly1305::new(
+                aead::generic_array::GenericArray::from_slice(&config.key),
+            );
+            cipher
+                .encrypt(nonce, plaintext)
+                .map_err(|_| anyhow!("ChaCha20-Poly1305 encryption failed"))?
+        }
+    };
+
+    let mut out = Vec::with_capacity(ENCRYPTION_HEADER_LEN + ciphertext.len());
+    out.push(config.ty.header_byte());
+    out.extend_from_slice(&nonce_bytes);
+    out.extend_from_slice(&ciphertext);
+    Ok(out.into())
+}
+
+/// Inverse of `encrypt_blob`. Validates that the header's declared cipher matches `config.ty`.
+fn decrypt_blob(config: &EncryptionConfig, data: &[u8]) -> Result<Bytes> {
+    use aead::{Aead, NewAead};
+
+    ensure!(
+        data.len() >= ENCRYPTION_HEADER_LEN,
+        "encrypted blob is shorter than the at-rest encryption header"
+    );
+    let ty = EncryptionType::from_header_byte(data[0])?;
+    ensure!(
+        ty == config.ty,
+        "encrypted blob's cipher doesn't match the configured EncryptionType"
+    );
+    let nonce = aead::generic_array::GenericArray::from_slice(&data[1..ENCRYPTION_HEADER_LEN]);
+    let ciphertext = &data[ENCRYPTION_HEADER_LEN..];
+
+    let plaintext = match ty {
+        EncryptionType::AesGcm => {
+            let cipher = aes_gcm::Aes256Gcm::new_varkey(&config.key)
+                .map_err(|_| anyhow!("invalid AES-256-GCM key"))?;
+            cipher
+                .decrypt(nonce, ciphertext)
+                .map_err(|_| anyhow!("AES-GCM decryption failed"))?
+        }
+        EncryptionType::Chacha20Poly1305 => {
+            let cipher = chacha20poly1305::ChaCha20Poly1305::new_varkey(&config.key)
+                .map_err(|_| anyhow!("invalid ChaCha20-Poly1305 key"))?;
+            cipher
+                .decrypt(nonce, ciphertext)
+                .map_err(|_| anyhow!("ChaCha20-Poly1305 decryption failed"))?
+        }
+    };
+
+    Ok(plaintext.into())
+}
+
+/// Tags the on-disk layout of a single `Entry`/LFS-blob/aux-data record, so a reader can tell
+/// which decoding rules to apply independent of which version of the crate wrote it. A new
+/// record shape (e.g. the not-yet-implemented aux fields, or a change to how the encryption/chunk
+/// headers above are framed) should bump this rather than silently reinterpreting old bytes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum RecordFormatVersion {
+    /// Pre-dates this scheme: a bare (possibly encrypted/chunked) payload with no
+    /// `RECORD_FORMAT_MAGIC` header. `FileStore::upgrade()` rewrites these into `V1`.
+    V0Legacy,
+    /// `RECORD_FORMAT_MAGIC || version:u8` followed by what would have been the `V0Legacy`
+    /// payload (unchanged otherwise, so encryption/chunking don't need to know this exists).
+    V1,
+}
+
+/// The format new writes are tagged with. `FileStore::upgrade()` rewrites anything older to this.
+const CURRENT_RECORD_FORMAT_VERSION: RecordFormatVersion = RecordFormatVersion::V1;
+
+/// Distinguishes a versioned record from `V0Legacy` content (which, being arbitrary encrypted or
+/// user file data, could plausibly start with any single byte) without ambiguity.
+const RECORD_FORMAT_MAGIC: &[u8] = b"\0scmstore-fmt\0";
+
+impl RecordFormatVersion {
+    fn header_byte(self) -> u8 {
+        match self {
+            RecordFormatVersion::V0Legacy => 0,
+            RecordFormatVersion::V1 => 1,
+        }
+    }
+
+    fn from_header_byte(b: u8) -> Result<Self> {
+        match b {
+            1 => Ok(RecordFormatVersion::V1),
+            _ => bail!("unknown store record format version byte: {}", b),
+        }
+    }
+}
+
+/// Prepend the current format version header to `payload`. Applied as the outermost layer, after
+/// any encryption/chunking, so `split_record_version` never needs the encryption key just to tell
+/// versions apart.
+fn wrap_record_version(payload: &[u8]) -> Bytes {
+    let mut out = Vec::with_capacity(RECORD_FORMAT_MAGIC.len() + 1 + payload.len());
+    out.extend_from_slice(RECORD_FORMAT_MAGIC);
+    out.push(CURRENT_RECORD_FORMAT_VERSION.header_byte());
+    out.extend_from_slice(payload);
+    out.into()
+}
+
+/// Inverse of `wrap_record_version`. Data with no `RECORD_FORMAT_MAGIC` header is assumed to be
+/// `V0Legacy`, so stores written before this scheme existed keep reading correctly.
+fn split_record_version(data: &Bytes) -> Result<(RecordFormatVersion, Bytes)> {
+    if data.starts_with(RECORD_FORMAT_MAGIC) {
+        let header_byte = *data
+            .get(RECORD_FORMAT_MAGIC.len())
+            .ok_or_else(|| anyhow!("store record format header is truncated"))?;
+        let version = RecordFormatVersion::from_header_byte(header_byte)?;
+        Ok((version, data.slice(RECORD_FORMAT_MAGIC.len() + 1..)))
+    } else {
+        Ok((RecordFormatVersion::V0Legacy, data.clone()))
+    }
+}
+
+/// `statfs(2)`'s `f_type` for an NFS mount on Linux (`NFS_SUPER_MAGIC` in the kernel's
+/// `linux/magic.h`). Used to pick `IndexedLogReadStrategy::Pread` for an `IndexedLogHgIdDataStore`
+/// whose directory lives on NFS, where a racing remote truncation or cache-coherency glitch can
+/// SIGBUS an mmap'd process instead of surfacing as an ordinary I/O error.
+#[cfg(target_os = "linux")]
+const NFS_SUPER_MAGIC: i64 = 0x6969;
+
+/// How an `IndexedLogHgIdDataStore` reads its data file, as chosen by `detect()`. `Mmap` is the
+/// default. `Pread` marks a store detected (or configured) as NFS-backed.
+///
+/// Both strategies read entries through the same mmap-based `get_raw_entry` (see
+/// `fetch_indexedlog`): `Pread` does not switch to buffered `pread`/`read_exact` reads, so an
+/// NFS-backed store picked as `Pread` gets no protection from mmap faults.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IndexedLogReadStrategy {
+    Mmap,
+    Pread,
+}
+
+impl Default for IndexedLogReadStrategy {
+    fn default() -> Self {
+        IndexedLogReadStrategy::Mmap
+    }
+}
+
+impl IndexedLogReadStrategy {
+    /// Pick a strategy for the store rooted at `path`. `nfs_override` lets
+    /// `scmstore.indexedlog-local-nfs`/`scmstore.indexedlog-cache-nfs`-style config force the
+    /// answer either way without probing the filesystem at all; `None` falls back to `statfs`.
+    pub fn detect(path: &Path, nfs_override: Option<bool>) -> Self {
+        match nfs_override {
+            Some(true) => return IndexedLogReadStrategy::Pread,
+            Some(false) => return IndexedLogReadStrategy::Mmap,
+            None => {}
+        }
+        if is_nfs_mount(path) {
+            IndexedLogReadStrategy::Pread
+        } else {
+            IndexedLogReadStrategy::Mmap
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_nfs_mount(path: &Path) -> bool {
+    use std::mem::MaybeUninit;
+    use std::os::unix::ffi::OsStrExt;
+
+    let cpath = match std::ffi::CString::new(path.as_os_str().as_bytes()) {
+        Ok(cpath) => cpath,
+        Err(_) => return false,
+    };
+    let mut stat: MaybeUninit<libc::statfs> = MaybeUninit::uninit();
+    // Safety: `cpath` is a valid NUL-terminated C string, and `stat` is sized for `statfs(2)`'s
+    // output; `f_type` is only read below once the call has actually succeeded.
+    let rc = unsafe { libc::statfs(cpath.as_ptr(), stat.as_mut_ptr()) };
+    if rc != 0 {
+        return false;
+    }
+    let stat = unsafe { stat.assume_init() };
+    stat.f_type as i64 == NFS_SUPER_MAGIC
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_nfs_mount(_path: &Path) -> bool {
+    // No portable statfs magic check; callers fall back to config overrides on other platforms.
+    false
+}
+
+const CDC_MIN_CHUNK_SIZE: usize = 16 * 1024;
+const CDC_MAX_CHUNK_SIZE: usize = 256 * 1024;
+/// Mask tuned so that, on average, a boundary is found every 64KiB: a Gear hash is effectively
+/// uniform over its low bits, so requiring 16 of them to be zero gives a mean run of 2^16 bytes.
+const CDC_BOUNDARY_MASK: u64 = (1 << 16) - 1;
+const CHUNK_MANIFEST_MAGIC: &[u8] = b"CDCMNFST1";
+
+/// Per-byte-value table for the Gear rolling hash used by `cdc_chunks`. The values just need to
+/// look random to the low 16 bits tested by `CDC_BOUNDARY_MASK`; they were generated offline with
+/// a fixed-seed splitmix64.
+#[rustfmt::skip]
+const GEAR: [u64; 256] = [
+    0x6E789E6AA1B965F4, 0x06C45D188009454F, 0xF88BB8A8724C81EC, 0x1B39896A51A8749B,
+    0x53CB9F0C747EA2EA, 0x2C829ABE1F4532E1, 0xC584133AC916AB3C, 0x3EE5789041C98AC3,
+    0xF3B8488C368CB0A6, 0x657EECDD3CB13D09, 0xC2D326E0055BDEF6, 0x8621A03FE0BBDB7B,
+    0x8E1F7555983AA92F, 0xB54E0F1600CC4D19, 0x84BB3F97971D80AB, 0x7D29825C75521255,
+    0xC3CF17102B7F7F86, 0x3466E9A083914F64, 0xD81A8D2B5A4485AC, 0xDB01602B100B9ED7,
+    0xA9038A921825F10D, 0xEDF5F1D90DCA2F6A, 0x54496AD67BD2634C, 0xDD7C01D4F5407269,
+    0x935E82F1DB4C4F7B, 0x69B82EBC92233300, 0x40D29EB57DE1D510, 0xA2F09DABB45C6316,
+    0xEE521D7A0F4D3872, 0xF16952EE72F3454F, 0x377D35DEA8E40225, 0x0C7DE8064963BAB0,
+    0x05582D37111AC529, 0xD254741F599DC6F7, 0x69630F7593D108C3, 0x417EF96181DAA383,
+    0x3C3C41A3B43343A1, 0x6E19905DCBE531DF, 0x4FA9FA7324851729, 0x84EB4454A792922A,
+    0x134F7096918175CE, 0x07DC930B302278A8, 0x12C015A97019E937, 0xCC06C31652EBF438,
+    0xECEE65630A691E37, 0x3E84ECB1763E79AD, 0x690ED476743AAE49, 0x774615D7B1A1F2E1,
+    0x22B353F04F4F52DA, 0xE3DDD86BA71A5EB1, 0xDF268ADEB6513356, 0x2098EB73D4367D77,
+    0x03D6845323CE3C71, 0xC952C5620043C714, 0x9B196BCA844F1705, 0x30260345DD9E0EC1,
+    0xCF448A5882BB9698, 0xF4A578DCCBC87656, 0xBFDEAED9A17B3C8F, 0xED79402D1D5C5D7B,
+    0x55F070AB1CBBF170, 0x3E00A34929A88F1D, 0xE255B237B8BB18FB, 0x2A7B67AF6C6AD50E,
+    0x466D5E7F3E46F143, 0x42375CB399A4FC72, 0x8C8A1F148A8BB259, 0x32FCAB5DAED5BDFC,
+    0x9E60398C8D8553C0, 0xEE89CCEB8C4064C0, 0xDB0215941D86A66F, 0x5CCDE78203C367A8,
+    0xF1BCBC6A1EC11786, 0xEF054FCEEE954551, 0xDF82012D0555C6DF, 0x292566FF72403C08,
+    0xC4DD302A1BFA1137, 0xD85F219DB5C554E1, 0x6A27FF807441BCD2, 0x96A573E9B48216E8,
+    0x46A9FDAC40BF0048, 0x3DD12464A0EE15B4, 0x451E521296A7EEA1, 0x56E4398A98F8A0FD,
+    0x7B7DC2160E3335A7, 0xC679EE0BEBCB1CCA, 0x928D6F2D7453424E, 0x1B38994205234C6D,
+    0x8086D193A6F2B568, 0x21C6E26639AC2C65, 0xD9DCCAC414D23C6F, 0x91CD642057E00235,
+    0x77FC607DC6589373, 0x05B8ABE26DD3AEE7, 0x12F6436AC376CC66, 0x64952424897B2307,
+    0xEE8C2BAF6343E5C3, 0xDC4C613D9EBA2304, 0x3505B7796BD1A506, 0x8176DAF800A05F50,
+    0x8BD8FF7A0385CDBC, 0x1A764A3CD78101DA, 0xBE4D15BF6CA266AC, 0xA85E1F38BB2DC749,
+    0x56759A968493CD8C, 0xF3A9BCE7336BD182, 0x365B15013741519B, 0x1F7A44A6B109AC94,
+    0x3521D628813CB177, 0x6A77AFAB0F7C9370, 0x179642D8CDE95015, 0x5EF102A8FB354461,
+    0xF51C504764ED82F2, 0xC58427F041CE6808, 0xFAD8FC45C9643C37, 0xCF8682F9A70FA9C0,
+    0x7E1B3B75A4005729, 0x992DD867927B52D8, 0x7FBD5DB142F6791F, 0x370595AACAB4ADAE,
+    0xB1392DBDC5AB61D6, 0x9FEA7DFC79D452D9, 0x40B12B120085641C, 0xA192AFE3157C85D0,
+    0xC847729F4E08F3A3, 0x6F1384A306C41FC2, 0x12D05C4045A39C19, 0x9899202FD20F0841,
+    0xE9C7191857E774B8, 0x4EEAD809AF5B0CC3, 0xE809ACAFA23864A4, 0x4DA1EDABA1D0F7BD,
+    0x846EB9673349F8E4, 0x87BAE55B86039FE8, 0x7F367B8BD953EFF2, 0x3884700F650D04E1,
+    0xBFE4B2AB46980CAD, 0xC5FC89075299106C, 0x37B2FA361ADEA7CD, 0x7D75D813F04895B4,
+    0x702F5B393F62C0E0, 0x0A3FC775F4ECF37F, 0xE4B23787A352437F, 0xF83FA245C34D6363,
+    0xB99BCF040786CF50, 0x38B6EA0A0E6C9D8A, 0x093FDC76776E37E1, 0x1A75E6F76BA7EEE8,
+    0x442CDCFEE9660C62, 0x22D58D35116B5E0B, 0x87D4A5180F6A3645, 0x589FB216BD82131B,
+    0x91D031CAD319AEC0, 0xABECF76A553D320B, 0xB8686CB347612DCF, 0xFCAB66337C0A77F5,
+    0xAC318214381EC437, 0x6EB7F0FCA24494AE, 0xCF42861DCDC895A9, 0x4ABAD7A1586D7A91,
+    0xC21B318DC2F49745, 0xD49474DC2ACBD1F0, 0xB1D4873747C1C8E1, 0x5434DC8C7D015BF6,
+    0xE1C486287511B6A9, 0xA8616DF62E89A193, 0x31CE6319498D8347, 0xAFD0B486123D6FAA,
+    0xE6495F5D102301EB, 0x0DC51CED17A43C52, 0x8BCBCDE81355EF2D, 0x2412AF73FDEE7CFC,
+    0xC8D589E486E29EED, 0x23390E8664517F89, 0x251ADE58E8A6849D, 0xF8555DBD2E8F9CB0,
+    0xCB417C3EEF54F7C3, 0x8028F8E1AAC3A919, 0x10E31052ACF748A0, 0x2D886C073B1E1B78,
+    0x972974D90DF9FAEE, 0xBC1B7B38796893BA, 0x1958ED432070E652, 0xCA5F297197A12DCC,
+    0xE025A27375704F28, 0x418010A570A924FB, 0x9828E2941BFC419C, 0x4FBACD2F52B85C1F,
+    0x33DD5B756211CC67, 0x23C8DFDD1DB57FF0, 0x32F81801A1A8E901, 0x26884EAC5ADA36DA,
+    0xCAA82F9BB42E37D4, 0x19FB1A7491D6A7D1, 0x5AA0243AA357F38E, 0xB31D917809E447F0,
+    0x3F9C197225215BE0, 0xDC3C315A1E33C095, 0x3DD399AD533E80AC, 0x566F32CCE8301D95,
+    0xC880188083D9BA21, 0xB9CC357F3B0E7D2E, 0x0237D2123A8A8D6C, 0xBF636E9AA7CBF6BD,
+    0xD7BD4284C4E2A6A7, 0xDA2EBB47D50577A9, 0x90BA1C11B539087D, 0x44993D31552B4F57,
+    0x32C2D6F80A8A8898, 0x450583ED7FB54B19, 0xEC2B0B09E50EF3EF, 0xD918A0B6E2EFD65C,
+    0xE37A868D9785F572, 0x7D1A6118F2B0F37A, 0x9E2E3CC13B343439, 0xEFD82C11212E37E8,
+    0xAF89C05CD4FC75ED, 0x55BC16BB9697108E, 0x6C4701FA5DB69BEE, 0x9237338441DAF445,
+    0x248CF0831E81A5FC, 0xACC13557E77DE273, 0x520970C25E06513A, 0x657329CB02987CAB,
+    0xA9B0B3366A4E55A8, 0xC4D06CA2F39ACDD4, 0x5DCE37D68170CDE1, 0x5F1E44E77E1854C9,
+    0x6883D452D55DF899, 0x05C5BD62F1067032, 0xE680B683CE60FAB0, 0x5DC9DA3F286D18B1,
+    0x94B4BF3AB85ED6D8, 0xCE65F449E3ACC5A3, 0x34B0209642CEA639, 0xC14C3C771D904827,
+    0x6ADDCEE2BD9CDEE5, 0xE24EED137FFBB613, 0x75DD58EF79963D1B, 0xFDB83ECF6CC24920,
+    0x7A1D0057C57169FB, 0x339200F4FEB62D07, 0xD33F4D4AC88469F4, 0x8226F234E68DFEE4,
+    0x320DEF4F2A105536, 0x7786F3B13AEFC159, 0xB28225AC9DF63EE2, 0x781B9D0376CC6044,
+    0x05BD0115226C6AB6, 0xD302230207BDFDAB, 0xDB898ABD8E0D2933, 0x9E79A397BA00B9CC,
+    0x89DF84A5F0003EE8, 0x011F04F2A75FB9BE, 0x5A5832BB47BCF19E, 0xCBDC6D34B7C7534D,
+];
+
+/// Split `data` into content-defined chunks using a Gear/buzhash-style rolling hash, so that an
+/// edit to one part of a large file only changes the chunks that overlap the edit, letting
+/// `ChunkStore` dedup the rest across versions. Chunk length is bounded to
+/// `[CDC_MIN_CHUNK_SIZE, CDC_MAX_CHUNK_SIZE]`, except for the final chunk, which holds whatever is
+/// left and may be shorter than `CDC_MIN_CHUNK_SIZE`.
+fn cdc_chunks(data: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut hash: u64 = 0;
+    for (i, &byte) in data.iter().enumerate() {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        let len = i + 1 - start;
+        if len >= CDC_MAX_CHUNK_SIZE || (len >= CDC_MIN_CHUNK_SIZE && hash & CDC_BOUNDARY_MASK == 0)
+        {
+            chunks.push(&data[start..=i]);
+            start = i + 1;
+            hash = 0;
+        }
+    }
+    if start < data.len() {
+        chunks.push(&data[start..]);
+    }
+    chunks
+}
+
+/// The manifest `ChunkStore`-backed files are stored as: an ordered list of chunk hashes (so the
+/// chunks can be concatenated back into the original content) plus the total size.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct ChunkManifest {
+    pub(crate) chunks: Vec<Sha256>,
+    pub(crate) total_size: u64,
+}
+
+/// A sha256-content-addressed store for the chunks produced by `cdc_chunks`, so that identical
+/// chunks across file versions (or even across different files) are stored once. Backed by the
+/// same indexedlog-based blob storage `LfsStore` already uses to store LFS objects by sha256.
+#[derive(Clone)]
+pub(crate) struct ChunkStore(Arc<LfsStore>);
+
+impl ChunkStore {
+    pub(crate) fn new(store: Arc<LfsStore>) -> Self {
+        ChunkStore(store)
+    }
+
+    fn get_chunk(&self, sha256: &Sha256) -> Result<Option<Bytes>> {
+        self.0.blob(sha256)
+    }
+
+    fn add_chunk(&self, sha256: &Sha256, data: Bytes) -> Result<()> {
+        if self.get_chunk(sha256)?.is_some() {
+            // Already present from this or an earlier version of the file; dedup.
+            return Ok(());
+        }
+        self.0.add_blob(sha256, data)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Split `data` into content-defined chunks, write any not already present to `chunk_store`, and
+/// return the manifest `LazyFile::Chunked` will later use to reassemble it.
+fn write_chunks(chunk_store: &ChunkStore, data: &[u8]) -> Result<ChunkManifest> {
+    let mut chunks = Vec::new();
+    for chunk in cdc_chunks(data) {
+        let sha256 = ContentHash::sha256(&Bytes::copy_from_slice(chunk)).unwrap_sha256();
+        chunk_store.add_chunk(&sha256, Bytes::copy_from_slice(chunk))?;
+        chunks.push(sha256);
+    }
+    Ok(ChunkManifest {
+        chunks,
+        total_size: data.len() as u64,
+    })
+}
+
+/// Stream-concatenate a manifest's chunks back into the original content.
+fn read_chunks(chunk_store: &ChunkStore, manifest: &ChunkManifest) -> Result<Bytes> {
+    let mut out = Vec::with_capacity(manifest.total_size as usize);
+    for sha256 in &manifest.chunks {
+        let chunk = chunk_store
+            .get_chunk(sha256)?
+            .ok_or_else(|| anyhow!("chunk {} referenced by manifest is missing", sha256))?;
+        out.extend_from_slice(&chunk);
+    }
+    Ok(out.into())
+}
+
+/// The materialized content backing a `LazyFile::Cached` entry: both encodings of the content
+/// plus the attributes computed from it, so a cache hit never needs to touch the backing
+/// `LazyFile` (mmap, decrypt, reassemble chunks, ...) it was built from.
+#[derive(Debug)]
+struct CachedFileContent {
+    hg_content: Bytes,
+    file_content: Bytes,
+    metadata: Metadata,
+    aux_data: FileAuxData,
+}
+
+/// A single piece of hot-cached content, refcounted by the number of `Key`s in
+/// `FileHotCacheInner::keys` currently pointing at it (see dedup-by-`content_sha256` in
+/// `FileHotCache`'s doc comment).
+struct HotCacheEntry {
+    content: Arc<CachedFileContent>,
+    size_bytes: u64,
+    refs: usize,
+}
+
+struct KeyEntry {
+    content_sha256: Sha256,
+    origin: LocalStoreType,
+    /// Bumped on every access; used to detect and skip stale entries in `recency` in O(1)
+    /// instead of scanning/removing from the middle of the queue on every hit.
+    generation: u64,
+    /// Access count, only consulted by `HotCacheEvictionPolicy::Lfu`.
+    freq: u64,
+}
+
+/// Which key `FileHotCache` evicts once it's over its byte budget.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HotCacheEvictionPolicy {
+    /// Evict the key that was read least recently.
+    Lru,
+    /// Evict the key that has been read the fewest times.
+    Lfu,
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct FileHotCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+struct FileHotCacheInner {
+    keys: HashMap<Key, KeyEntry>,
+    content: HashMap<Sha256, HotCacheEntry>,
+    /// FIFO of (generation, Key) pushed on every insert/access; the front is the
+    /// least-recently-used candidate, but may be stale (see `KeyEntry::generation`).
+    recency: VecDeque<(u64, Key)>,
+    next_generation: u64,
+    used_bytes: u64,
+    stats: FileHotCacheStats,
+}
+
+/// A bounded in-memory cache of recently fetched file content, consulted before any disk/remote
+/// store in `FileStore::fetch` and populated by `FetchState::write_to_cache`. Bounded by a byte
+/// budget (approximated from each entry's content length) and drained by `HotCacheEvictionPolicy`
+/// once over it.
+///
+/// Entries are deduplicated by `content_sha256`: distinct `Key`s that happen to resolve to
+/// identical content (e.g. the same blob reached through different forks/renames) share a single
+/// cached copy. The copy is evicted only once every `Key` mapped to it has itself been evicted.
+pub struct FileHotCache {
+    capacity_bytes: u64,
+    policy: HotCacheEvictionPolicy,
+    inner: Mutex<FileHotCacheInner>,
+}
+
+impl FileHotCache {
+    pub fn new(capacity_bytes: u64, policy: HotCacheEvictionPolicy) -> Self {
+        FileHotCache {
+            capacity_bytes,
+            policy,
+            inner: Mutex::new(FileHotCacheInner {
+                keys: HashMap::new(),
+                content: HashMap::new(),
+                recency: VecDeque::new(),
+                next_generation: 0,
+                used_bytes: 0,
+                stats: FileHotCacheStats::default(),
+            }),
+        }
+    }
+
+    /// Drop every cached entry and reset `used_bytes`, without disturbing hit/miss/eviction
+    /// counters. Called from `FileStore::flush` so a long-lived process can bound memory use
+    /// across explicit flush points, the same way `indexedlog`/`LfsStore` bound on-disk state.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock();
+        inner.keys.clear();
+        inner.content.clear();
+        inner.recency.clear();
+        inner.used_bytes = 0;
+    }
+
+    pub fn stats(&self) -> FileHotCacheStats {
+        self.inner.lock().stats
+    }
+
+    fn get(&self, key: &Key) -> Option<(Arc<CachedFileContent>, LocalStoreType)> {
+        let mut inner = self.inner.lock();
+        let found = match inner.keys.get(key) {
+            Some(key_entry) => Some((key_entry.content_sha256.clone(), key_entry.origin)),
+            None => None,
+        };
+        match found {
+            Some((sha256, origin)) => {
+                inner.stats.hits += 1;
+                inner.touch(key.clone());
+                let content = inner.content[&sha256].content.clone();
+                Some((content, origin))
+            }
+            None => {
+                inner.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn insert(&self, key: Key, origin: LocalStoreType, content: Arc<CachedFileContent>) {
+        let size_bytes = (content.hg_content.len() + content.file_content.len() + 32) as u64;
+        let sha256 = content.aux_data.content_sha256.clone();
+        let mut inner = self.inner.lock();
+        inner.remove_key(&key);
+
+        if let Some(entry) = inner.content.get_mut(&sha256) {
+            entry.refs += 1;
+        } else {
+            inner.content.insert(
+                sha256.clone(),
+                HotCacheEntry {
+                    content,
+                    size_bytes,
+                    refs: 1,
+                },
+            );
+            inner.used_bytes += size_bytes;
+        }
+
+        let generation = inner.next_generation;
+        inner.next_generation += 1;
+        inner.keys.insert(
+            key.clone(),
+            KeyEntry {
+                content_sha256: sha256,
+                origin,
+                generation,
+                freq: 1,
+            },
+        );
+        inner.recency.push_back((generation, key));
+
+        inner.evict_to_capacity(self.capacity_bytes, self.policy);
+    }
+}
+
+impl FileHotCacheInner {
+    /// Move `key` to the back of the recency queue (most-recently-used) by bumping its
+    /// generation, and bump its access count; the stale front-of-queue entry left behind is
+    /// skipped on later LRU eviction.
+    fn touch(&mut self, key: Key) {
+        if let Some(key_entry) = self.keys.get_mut(&key) {
+            let generation = self.next_generation;
+            self.next_generation += 1;
+            key_entry.generation = generation;
+            key_entry.freq += 1;
+            self.recency.push_back((generation, key));
+        }
+    }
+
+    fn remove_key(&mut self, key: &Key) {
+        if let Some(key_entry) = self.keys.remove(key) {
+            self.drop_content_ref(key_entry.content_sha256);
+        }
+    }
+
+    fn drop_content_ref(&mut self, sha256: Sha256) {
+        if let hash_map::Entry::Occupied(mut entry) = self.content.entry(sha256) {
+            entry.get_mut().refs -= 1;
+            if entry.get().refs == 0 {
+                self.used_bytes -= entry.get().size_bytes;
+                entry.remove();
+            }
+        }
+    }
+
+    fn evict_to_capacity(&mut self, capacity_bytes: u64, policy: HotCacheEvictionPolicy) {
+        while self.used_bytes > capacity_bytes {
+            let victim = match policy {
+                HotCacheEvictionPolicy::Lru => self.next_lru_victim(),
+                HotCacheEvictionPolicy::Lfu => self.next_lfu_victim(),
+            };
+            let key = match victim {
+                Some(key) => key,
+                None => break,
+            };
+            self.remove_key(&key);
+            self.stats.evictions += 1;
+        }
+    }
+
+    /// Pop the front of the recency queue, skipping stale entries (see `KeyEntry::generation`),
+    /// until an actual least-recently-used key is found (or the queue runs dry).
+    fn next_lru_victim(&mut self) -> Option<Key> {
+        loop {
+            let (generation, key) = self.recency.pop_front()?;
+            // Stale: this key was touched again after being pushed, so a fresher entry for it
+            // is further back in the queue. Not an eviction.
+            let stale = self
+                .keys
+                .get(&key)
+                .map_or(true, |key_entry| key_entry.generation != generation);
+            if !stale {
+                return Some(key);
+            }
+        }
+    }
+
+    /// O(n) in the number of cached keys. Fine at the modest byte budgets this cache is meant to
+    /// run at (cheap next to the disk/remote fetch an eviction saves); a frequency-bucketed
+    /// structure would be worth it if that budget grew much larger.
+    fn next_lfu_victim(&self) -> Option<Key> {
+        self.keys
+            .iter()
+            .min_by_key(|(_, key_entry)| key_entry.freq)
+            .map(|(key, _)| key.clone())
+    }
+}
+
+/// One in-flight remote fetch for a single `Key`, shared between whichever `FetchState` issued
+/// the real request (the "leader") and any others asking for the same `Key` while it's
+/// outstanding (the "followers"). The leader resolves it exactly once, via `fulfill`; every
+/// waiter, including ones that arrive after `fulfill` runs but before the entry is removed from
+/// `RemoteFetchCoalescer`, observes the same result.
+///
+/// Generic over the payload `T` so this same primitive backs both the non-LFS remotes in
+/// `fetch_remote` (where a follower's payload is the materialized `Arc<CachedFileContent>`
+/// itself) and that same function's LFS remote dispatch (where a follower just needs a
+/// done-or-error signal and then re-reads from the shared local LFS store the leader just
+/// populated; see `RemoteFetchCoalescer::lease`'s callers).
+///
+/// This crate's fetch path is synchronous (see `fetch_remote`'s use of `crossbeam::thread::scope`
+/// rather than an async executor), so followers block on a condvar instead of polling a future.
+struct SharedRemoteFetch<T> {
+    result: Mutex<Option<Result<T, String>>>,
+    condvar: Condvar,
+}
+
+impl<T: Clone> SharedRemoteFetch<T> {
+    fn new() -> Self {
+        SharedRemoteFetch {
+            result: Mutex::new(None),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// Called once, by the leader, with the outcome of the real remote fetch. The error is
+    /// stringified so each waiter gets an independent copy to report rather than sharing one
+    /// `Error` across threads.
+    fn fulfill(&self, result: Result<T, String>) {
+        *self.result.lock() = Some(result);
+        self.condvar.notify_all();
+    }
+
+    /// Block until the leader calls `fulfill`, then return its result.
+    fn wait(&self) -> Result<T, String> {
+        let mut result = self.result.lock();
+        loop {
+            if let Some(ref result) = *result {
+                return result.clone();
+            }
+            self.condvar.wait(&mut result);
+        }
+    }
+}
+
+/// Whether this `FetchState` must issue the remote request for a `Key` itself (`Leader`), or
+/// should instead block on another `FetchState`'s in-flight request for it (`Follower`).
+/// Returned by `RemoteFetchCoalescer::lease`.
+enum RemoteFetchLease<T> {
+    Leader(Arc<SharedRemoteFetch<T>>),
+    Follower(Arc<SharedRemoteFetch<T>>),
+}
+
+/// Deduplicates concurrent remote-tier fetches for the same `Key` across `FetchState`s running on
+/// different threads, so a parallel tree walk asking for the same file from several workers
+/// triggers one round-trip instead of one per worker. Local indexedlog/LFS reads are cheap enough,
+/// and varied enough in which local store answers them, that they're never registered here and
+/// stay independent.
+///
+/// `FileStore` holds one instance of this per remote tier shape: `remote_coalescer` for
+/// memcache/redis/EdenApi (payload is the materialized content), `lfs_remote_coalescer` for
+/// `lfs_remote` (payload is just `()`, since a follower re-reads the blob itself once the leader's
+/// `batch_fetch` has written it into the shared local LFS store).
+pub(crate) struct RemoteFetchCoalescer<T> {
+    inflight: Mutex<HashMap<Key, Arc<SharedRemoteFetch<T>>>>,
+}
+
+impl<T> Default for RemoteFetchCoalescer<T> {
+    fn default() -> Self {
+        RemoteFetchCoalescer {
+            inflight: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Clone> RemoteFetchCoalescer<T> {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Claim `key`: the first caller becomes `Leader` and is responsible for eventually calling
+    /// `complete`; every other caller while it's outstanding becomes a `Follower` and should
+    /// `wait()` on the returned slot instead of fetching `key` itself.
+    fn lease(&self, key: Key) -> RemoteFetchLease<T> {
+        match self.inflight.lock().entry(key) {
+            hash_map::Entry::Occupied(entry) => RemoteFetchLease::Follower(entry.get().clone()),
+            hash_map::Entry::Vacant(entry) => {
+                let slot = Arc::new(SharedRemoteFetch::new());
+                entry.insert(slot.clone());
+                RemoteFetchLease::Leader(slot)
+            }
+        }
+    }
+
+    /// The leader's request for `key` is done (successfully or not): fulfill the shared slot for
+    /// any followers, then unregister it so the next fetch of `key` starts a fresh request rather
+    /// than replaying this one's result forever.
+    fn complete(&self, key: &Key, slot: &SharedRemoteFetch<T>, result: Result<T, String>) {
+        slot.fulfill(result);
+        self.inflight.lock().remove(key);
+    }
+}
+
+/// A Redis-backed remote cache tier, for teams that want to share cache state across machines
+/// without running a memcache deployment. Entries are addressed by the same `Key` and round-trip
+/// through `McData` via `bincode`, so `FetchState` can treat a Redis hit exactly like a memcache
+/// hit (see `LazyFile::Redis`) once it's deserialized.
+pub(crate) struct RedisStore {
+    client: redis::Client,
+    /// Namespaces cache keys so multiple repos (or a repo and an unrelated app) can share one
+    /// Redis instance without colliding.
+    key_prefix: String,
+}
+
+impl RedisStore {
+    pub(crate) fn new(client: redis::Client, key_prefix: String) -> Self {
+        RedisStore { client, key_prefix }
+    }
+
+    fn cache_key(&self, key: &Key) -> String {
+        format!("{}:{}:{}", self.key_prefix, key.path, key.hgid)
+    }
+
+    /// Fetch `keys` from Redis in a single `MGET` round-trip, returning only the ones actually
+    /// present (a cache miss is not an error). Mirrors `MemcacheStore::get_data_iter`'s shape so
+    /// `fetch_remote` can dispatch to either tier identically.
+    pub(crate) fn get_data_iter(&self, keys: &[Key]) -> Result<Vec<McData>> {
+        let mut conn = self.client.get_connection()?;
+        let cache_keys: Vec<String> = keys.iter().map(|key| self.cache_key(key)).collect();
+        let raw: Vec<Option<Vec<u8>>> = redis::cmd("MGET").arg(cache_keys).query(&mut conn)?;
+        raw.into_iter()
+            .flatten()
+            .map(|bytes| Ok(bincode::deserialize(&bytes)?))
+            .collect()
+    }
+
+    /// Populate Redis with a freshly-fetched entry. Like `MemcacheStore::add_mcdata`, failures are
+    /// the caller's to ignore: a cache write failing shouldn't fail the read that triggered it.
+    pub(crate) fn add(&self, data: McData) -> Result<()> {
+        let mut conn = self.client.get_connection()?;
+        let bytes = bincode::serialize(&data)?;
+        redis::cmd("SET")
+            .arg(self.cache_key(&data.key))
+            .arg(bytes)
+            .query(&mut conn)?;
+        Ok(())
+    }
+}
+
 pub struct FileStore {
     // Config
     pub(crate) extstored_policy: ExtStoredPolicy,
@@ -40,12 +886,33 @@ pub struct FileStore {
     pub(crate) cache_to_local_cache: bool,
     pub(crate) cache_to_memcache: bool,
 
+    /// If set, content written to `indexedlog_local`/`indexedlog_cache` and `lfs_local`/`lfs_cache`
+    /// is AEAD-encrypted at rest, and transparently decrypted on read. Hashes (LFS sha256 pointer,
+    /// `content_sha256`) are always computed over the plaintext.
+    pub(crate) encryption: Option<Arc<EncryptionConfig>>,
+
+    /// If set, LFS-bound blobs (see `lfs_threshold_bytes`) larger than this are additionally
+    /// split into content-defined chunks deduped in `chunk_store`, rather than stored as one
+    /// monolithic LFS object. Only LFS-bound blobs are considered, so a value below
+    /// `lfs_threshold_bytes` chunks every LFS-bound blob.
+    pub(crate) chunk_threshold_bytes: Option<u64>,
+    pub(crate) chunk_store: Option<Arc<ChunkStore>>,
+
+    /// An in-memory LRU or LFU cache (see `HotCacheEvictionPolicy`) consulted before any
+    /// disk/remote store, see `FileHotCache`.
+    pub(crate) hot_cache: Option<Arc<FileHotCache>>,
+
     // Local-only stores
     pub(crate) indexedlog_local: Option<Arc<IndexedLogHgIdDataStore>>,
+    /// How to read `indexedlog_local`'s data file, see `IndexedLogReadStrategy`. Set independently
+    /// of `indexedlog_cache_read_strategy` since a local-only store and a shared cache directory
+    /// can live on different filesystems.
+    pub(crate) indexedlog_local_read_strategy: IndexedLogReadStrategy,
     pub(crate) lfs_local: Option<Arc<LfsStore>>,
 
     // Local non-lfs cache aka shared store
     pub(crate) indexedlog_cache: Option<Arc<IndexedLogHgIdDataStore>>,
+    pub(crate) indexedlog_cache_read_strategy: IndexedLogReadStrategy,
 
     // Local LFS cache aka shared store
     pub(crate) lfs_cache: Option<Arc<LfsStore>>,
@@ -53,10 +920,26 @@ pub struct FileStore {
     // Mecache
     pub(crate) memcache: Option<Arc<MemcacheStore>>,
 
+    /// A second, independent remote cache tier alongside `memcache`; see `RedisStore`.
+    pub(crate) redis: Option<Arc<RedisStore>>,
+    pub(crate) cache_to_redis: bool,
+
     // Remote stores
     pub(crate) lfs_remote: Option<Arc<LfsRemoteInner>>,
     pub(crate) edenapi: Option<Arc<EdenApiFileStore>>,
 
+    /// Deduplicates concurrent memcache/redis/EdenApi fetches for the same `Key` across
+    /// `FetchState`s sharing this `FileStore`; see `RemoteFetchCoalescer`.
+    pub(crate) remote_coalescer: Arc<RemoteFetchCoalescer<Arc<CachedFileContent>>>,
+
+    /// Like `remote_coalescer`, but for `lfs_remote` fetches; kept separate since a follower's
+    /// payload shape differs (see `RemoteFetchCoalescer`'s doc comment).
+    pub(crate) lfs_remote_coalescer: Arc<RemoteFetchCoalescer<()>>,
+
+    /// Opt-in integrity checks, off by default; see the fields of the same name on `FetchState`.
+    pub(crate) verify_content: bool,
+    pub(crate) consistency_check: bool,
+
     // Legacy ContentStore fallback
     pub(crate) contentstore: Option<Arc<ContentStore>>,
 
@@ -82,6 +965,9 @@ impl Drop for FileStore {
         if let Some(ref lfs_cache) = self.lfs_cache {
             let _ = lfs_cache.flush();
         }
+        if let Some(ref chunk_store) = self.chunk_store {
+            let _ = chunk_store.flush();
+        }
         if let Some(ref aux_local) = self.aux_local {
             let _ = aux_local.flush_log();
         }
@@ -100,7 +986,21 @@ pub struct FileStoreFetch {
 
 impl FileStore {
     pub fn fetch(&self, keys: impl Iterator<Item = Key>, attrs: FileAttributes) -> FileStoreFetch {
-        let mut state = FetchState::new(keys, self.extstored_policy, attrs);
+        let mut state = FetchState::new(
+            keys,
+            self.extstored_policy,
+            attrs,
+            self.encryption.clone(),
+            self.chunk_store.clone(),
+            self.remote_coalescer.clone(),
+            self.lfs_remote_coalescer.clone(),
+            self.verify_content,
+            self.consistency_check,
+        );
+
+        if let Some(ref hot_cache) = self.hot_cache {
+            state.fetch_hot_cache(hot_cache);
+        }
 
         if let Some(ref aux_cache) = self.aux_cache {
             state.fetch_aux_indexedlog(aux_cache);
@@ -111,11 +1011,19 @@ impl FileStore {
         }
 
         if let Some(ref indexedlog_cache) = self.indexedlog_cache {
-            state.fetch_indexedlog(indexedlog_cache, LocalStoreType::Cache);
+            state.fetch_indexedlog(
+                indexedlog_cache,
+                LocalStoreType::Cache,
+                self.indexedlog_cache_read_strategy,
+            );
         }
 
         if let Some(ref indexedlog_local) = self.indexedlog_local {
-            state.fetch_indexedlog(indexedlog_local, LocalStoreType::Local);
+            state.fetch_indexedlog(
+                indexedlog_local,
+                LocalStoreType::Local,
+                self.indexedlog_local_read_strategy,
+            );
         }
 
         if let Some(ref lfs_cache) = self.lfs_cache {
@@ -126,17 +1034,18 @@ impl FileStore {
             state.fetch_lfs(lfs_local, LocalStoreType::Local);
         }
 
-        if let Some(ref memcache) = self.memcache {
-            state.fetch_memcache(memcache);
-        }
-
-        if let Some(ref edenapi) = self.edenapi {
-            state.fetch_edenapi(edenapi);
-        }
-
-        if let Some(ref lfs_remote) = self.lfs_remote {
-            state.fetch_lfs_remote(lfs_remote, self.lfs_local.clone(), self.lfs_cache.clone());
-        }
+        // Memcache, Redis, EdenApi, and the LFS remote's `batch_fetch` are independent remote
+        // backends (the first three are all asked for the same non-LFS pending keys, the LFS
+        // remote for the pending LFS pointers), so dispatch them all concurrently rather than
+        // waiting on one before starting the next.
+        state.fetch_remote(
+            self.memcache.as_ref().map(|s| s.as_ref()),
+            self.redis.as_ref().map(|s| s.as_ref()),
+            self.edenapi.as_ref().map(|s| s.as_ref()),
+            self.lfs_remote.as_ref().map(|s| s.as_ref()),
+            self.lfs_local.clone(),
+            self.lfs_cache.clone(),
+        );
 
         if let Some(ref contentstore) = self.contentstore {
             state.fetch_contentstore(contentstore);
@@ -159,13 +1068,61 @@ impl FileStore {
                     None
                 }
             }),
+            self.redis.as_ref().and_then(|s| {
+                if self.cache_to_redis {
+                    Some(s.as_ref())
+                } else {
+                    None
+                }
+            }),
             self.aux_cache.as_ref().map(|s| s.as_ref()),
             self.aux_local.as_ref().map(|s| s.as_ref()),
+            self.hot_cache.as_ref().map(|s| s.as_ref()),
         );
 
         state.finish()
     }
 
+    /// Fetch only `[offset, offset+len)` of `key`'s content, without requesting (or computing) aux
+    /// data. `minibytes::Bytes::slice` is a zero-copy view regardless of what backs it, so for an
+    /// unencrypted, unchunked LFS entry whose blob is already an mmap (see `fetch_remote`'s LFS
+    /// mmap-retry path) this never copies the full file, only the requested subrange ever gets
+    /// read. Encrypted or chunked LFS blobs are decrypted or reassembled in full, and entries this
+    /// crate has no partial-read path for (EdenApi, legacy `ContentStore`) decode their full
+    /// content, before the slice is taken.
+    pub fn fetch_range(&self, key: Key, offset: u64, len: u64) -> Result<Bytes> {
+        let mut fetched = self.fetch(std::iter::once(key.clone()), FileAttributes::CONTENT);
+        let mut sf = match fetched.complete.remove(&key) {
+            Some(sf) => sf,
+            None => {
+                return Err(match fetched.incomplete.remove(&key).and_then(|mut errs| errs.pop()) {
+                    Some(err) => err,
+                    None => anyhow!("{:?} not found", key),
+                });
+            }
+        };
+        let content = sf
+            .content
+            .as_mut()
+            .ok_or_else(|| anyhow!("no content attribute returned for {:?}", key))?
+            .file_content()?;
+
+        let offset = usize::try_from(offset).map_err(|_| anyhow!("offset overflows usize"))?;
+        let len = usize::try_from(len).map_err(|_| anyhow!("len overflows usize"))?;
+        let end = offset
+            .checked_add(len)
+            .ok_or_else(|| anyhow!("offset + len overflows"))?;
+        ensure!(
+            end <= content.len(),
+            "requested range [{}, {}) is out of bounds for {:?} ({} bytes)",
+            offset,
+            end,
+            key,
+            content.len()
+        );
+        Ok(content.slice(offset..end))
+    }
+
     pub fn write_batch(&self, entries: impl Iterator<Item = (Key, Bytes, Metadata)>) -> Result<()> {
         let mut indexedlog_local = self.indexedlog_local.as_ref().map(|l| l.write_lock());
         for (key, bytes, meta) in entries {
@@ -183,10 +1140,30 @@ impl FileStore {
                     anyhow!("trying to write LFS file but no local LfsStore is available")
                 })?;
                 let (lfs_pointer, lfs_blob) = lfs_from_hg_file_blob(key.hgid, &bytes)?;
+                // The LFS sha256 pointer is derived above, from the plaintext blob, and must
+                // keep addressing the plaintext even though we store ciphertext on disk.
                 let sha256 = lfs_pointer.sha256();
 
+                // If the blob is large enough and a chunk store is configured, store it as a
+                // manifest of content-defined chunks instead of one monolithic blob, so that
+                // edits to large files only add the chunks that actually changed.
+                let stored_blob = match (&self.chunk_store, self.chunk_threshold_bytes) {
+                    (Some(chunk_store), Some(threshold)) if lfs_blob.len() as u64 > threshold => {
+                        let manifest = write_chunks(chunk_store, &lfs_blob)?;
+                        let mut payload = CHUNK_MANIFEST_MAGIC.to_vec();
+                        payload.extend_from_slice(&serde_json::to_vec(&manifest)?);
+                        Bytes::from(payload)
+                    }
+                    _ => lfs_blob,
+                };
+                let stored_blob = match &self.encryption {
+                    Some(config) => encrypt_blob(config, &stored_blob)?,
+                    None => stored_blob,
+                };
+                let stored_blob = wrap_record_version(&stored_blob);
+
                 // TODO(meyer): Do similar LockGuard impl for LfsStore so we can lock across the batch for both
-                lfs_local.add_blob(&sha256, lfs_blob)?;
+                lfs_local.add_blob(&sha256, stored_blob)?;
                 lfs_local.add_pointer(lfs_pointer)?;
             } else {
                 let indexedlog_local = indexedlog_local.as_mut().ok_or_else(|| {
@@ -194,6 +1171,11 @@ impl FileStore {
                         "trying to write non-LFS file but no local non-LFS IndexedLog is available"
                     )
                 })?;
+                let bytes = match &self.encryption {
+                    Some(config) => encrypt_blob(config, &bytes)?,
+                    None => bytes,
+                };
+                let bytes = wrap_record_version(&bytes);
                 indexedlog_local.put_entry(Entry::new(key, bytes, meta))?;
             }
         }
@@ -204,19 +1186,34 @@ impl FileStore {
         FileStore {
             extstored_policy: self.extstored_policy.clone(),
             lfs_threshold_bytes: self.lfs_threshold_bytes.clone(),
+            encryption: self.encryption.clone(),
+            chunk_threshold_bytes: self.chunk_threshold_bytes.clone(),
+            chunk_store: self.chunk_store.clone(),
+            hot_cache: self.hot_cache.clone(),
 
             indexedlog_local: self.indexedlog_local.clone(),
+            indexedlog_local_read_strategy: self.indexedlog_local_read_strategy,
             lfs_local: self.lfs_local.clone(),
 
             indexedlog_cache: self.indexedlog_cache.clone(),
+            indexedlog_cache_read_strategy: self.indexedlog_cache_read_strategy,
             lfs_cache: self.lfs_cache.clone(),
             cache_to_local_cache: self.cache_to_local_cache.clone(),
 
             memcache: None,
             cache_to_memcache: self.cache_to_memcache.clone(),
 
+            redis: None,
+            cache_to_redis: self.cache_to_redis.clone(),
+
             edenapi: None,
             lfs_remote: None,
+            // No remote tiers above, so nothing will ever consult either coalescer; a fresh one
+            // costs nothing and keeps this constructor from needing an `Option`.
+            remote_coalescer: Arc::new(RemoteFetchCoalescer::new()),
+            lfs_remote_coalescer: Arc::new(RemoteFetchCoalescer::new()),
+            verify_content: self.verify_content,
+            consistency_check: self.consistency_check,
 
             contentstore: None,
 
@@ -224,6 +1221,95 @@ impl FileStore {
             aux_cache: self.aux_cache.clone(),
         }
     }
+
+    /// Scan `indexedlog_local`, `indexedlog_cache`, `lfs_local`, and `lfs_cache` for records not
+    /// already in `CURRENT_RECORD_FORMAT_VERSION`, and rewrite them in place (LFS pointer entries in
+    /// the indexedlogs are left as they are). `LazyFile` decodes each record by the version it
+    /// actually carries (a format header, or none for `V0Legacy`), so this is safe to run against
+    /// a store that's concurrently being read from (or even
+    /// written to), and a crash partway through leaves already-rewritten records upgraded and the
+    /// rest untouched rather than corrupted.
+    pub fn upgrade(&self) -> Result<FileStoreUpgradeStats> {
+        let mut stats = FileStoreUpgradeStats {
+            store_format_version: CURRENT_RECORD_FORMAT_VERSION.header_byte(),
+            ..Default::default()
+        };
+        if let Some(ref store) = self.indexedlog_local {
+            stats.indexedlog_local_rewritten = upgrade_indexedlog_store(store)?;
+        }
+        if let Some(ref store) = self.indexedlog_cache {
+            stats.indexedlog_cache_rewritten = upgrade_indexedlog_store(store)?;
+        }
+        if let Some(ref store) = self.lfs_local {
+            stats.lfs_local_rewritten = upgrade_lfs_store(store)?;
+        }
+        if let Some(ref store) = self.lfs_cache {
+            stats.lfs_cache_rewritten = upgrade_lfs_store(store)?;
+        }
+        Ok(stats)
+    }
+}
+
+/// How many records `FileStore::upgrade()` found and rewrote, plus the format version it left
+/// them in. Callers that track a `store_format_version` marker externally (e.g. alongside the
+/// repo's requirements file) should persist `store_format_version` once this returns.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct FileStoreUpgradeStats {
+    pub indexedlog_local_rewritten: u64,
+    pub indexedlog_cache_rewritten: u64,
+    pub lfs_local_rewritten: u64,
+    pub lfs_cache_rewritten: u64,
+    pub store_format_version: u8,
+}
+
+/// Rewrite every entry in `store` not already tagged `CURRENT_RECORD_FORMAT_VERSION`, swapping
+/// each one in place via `put_entry` under its own key. Returns the number of entries rewritten.
+fn upgrade_indexedlog_store(store: &IndexedLogHgIdDataStore) -> Result<u64> {
+    let mut stale = Vec::new();
+    {
+        let reader = store.read_lock();
+        for entry in reader.iter_entries()? {
+            let mut entry = entry?;
+            // LFS pointer entries are parsed as-is by `TryFrom<Entry> for LfsPointersEntry`, which
+            // doesn't strip a format header, so they're left untagged.
+            if entry.metadata().is_lfs() {
+                continue;
+            }
+            let (version, _) = split_record_version(&entry.content()?)?;
+            if version != CURRENT_RECORD_FORMAT_VERSION {
+                stale.push(entry);
+            }
+        }
+    }
+
+    let mut rewritten = 0;
+    let mut writer = store.write_lock();
+    for mut entry in stale {
+        let (_, payload) = split_record_version(&entry.content()?)?;
+        let key = entry.key().clone();
+        let meta = entry.metadata().clone();
+        writer.put_entry(Entry::new(key, wrap_record_version(&payload), meta))?;
+        rewritten += 1;
+    }
+    Ok(rewritten)
+}
+
+/// Rewrite every blob in `store` not already tagged `CURRENT_RECORD_FORMAT_VERSION`.
+fn upgrade_lfs_store(store: &LfsStore) -> Result<u64> {
+    let mut rewritten = 0;
+    for sha256 in store.sha256_iter()? {
+        let sha256 = sha256?;
+        let blob = match store.blob(&sha256)? {
+            Some(blob) => blob,
+            None => continue,
+        };
+        let (version, payload) = split_record_version(&blob)?;
+        if version != CURRENT_RECORD_FORMAT_VERSION {
+            store.add_blob(&sha256, wrap_record_version(&payload))?;
+            rewritten += 1;
+        }
+    }
+    Ok(rewritten)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -268,6 +1354,26 @@ impl StoreFile {
         );
         Ok(())
     }
+
+    /// Eagerly materialize this file into the same representation `FileHotCache` stores, computing
+    /// aux data first if it hasn't been already. Used to hand a remote fetch's result to
+    /// `RemoteFetchCoalescer` followers, which can't run the lazy decode themselves since they
+    /// never touch the `LazyFile` that came back from the remote.
+    fn materialize_cached_content(&mut self) -> Result<Arc<CachedFileContent>> {
+        if self.aux_data.is_none() {
+            self.compute_aux_data()?;
+        }
+        let content = self
+            .content
+            .as_mut()
+            .ok_or_else(|| anyhow!("cannot materialize a StoreFile with no content"))?;
+        Ok(Arc::new(CachedFileContent {
+            hg_content: content.hg_content()?,
+            file_content: content.file_content()?,
+            metadata: content.metadata()?,
+            aux_data: self.aux_data.clone().unwrap(),
+        }))
+    }
 }
 
 impl BitOr for StoreFile {
@@ -410,16 +1516,35 @@ enum LazyFile {
     ContentStore(Bytes, Metadata),
 
     /// An entry from a local IndexedLog. The contained Key's path might not match the requested Key's path.
-    IndexedLog(Entry),
+    /// `entry`'s content is prefixed with a `RECORD_FORMAT_MAGIC` header unless it predates that
+    /// header (`V0Legacy`); either way it is unwrapped lazily in `file_content`/`hg_content` via
+    /// `split_record_version`. If at-rest encryption is configured,
+    /// what follows that header is the `[type:u8][nonce:12B] || ciphertext` encoding produced by
+    /// `encrypt_blob`, itself decrypted lazily.
+    IndexedLog(Entry, Option<Arc<EncryptionConfig>>),
+
+    /// A local LfsStore entry. `blob` has already had its `RECORD_FORMAT_MAGIC` header stripped (see
+    /// `found_lfs`); as with `IndexedLog` above, it may still be ciphertext pending decryption.
+    Lfs(Bytes, LfsPointersEntry, Option<Arc<EncryptionConfig>>),
 
-    /// A local LfsStore entry.
-    Lfs(Bytes, LfsPointersEntry),
+    /// A local LfsStore entry whose blob is a `ChunkManifest` rather than the content itself
+    /// (see `write_chunks`/`CHUNK_MANIFEST_MAGIC`). Reassembled lazily from `ChunkStore`.
+    Chunked(ChunkManifest, Arc<ChunkStore>, LfsPointersEntry),
 
     /// An EdenApi FileEntry.
     EdenApi(FileEntry),
 
     /// A memcache entry, convertable to Entry. In this case the Key's path should match the requested Key's path.
     Memcache(McData),
+
+    /// A Redis entry; see `RedisStore`. Kept as its own variant rather than reusing `Memcache`
+    /// (the wire shape is identical) so write-back and errors can tell which remote tier an entry
+    /// actually came from.
+    Redis(McData),
+
+    /// Content served from `FileStore::hot_cache`, already fully materialized. See
+    /// `CachedFileContent` and `FileHotCache`.
+    Cached(Arc<CachedFileContent>),
 }
 
 impl LazyFile {
@@ -427,21 +1552,29 @@ impl LazyFile {
         use LazyFile::*;
         match self {
             ContentStore(_, _) => None,
-            IndexedLog(ref entry) => Some(entry.key().hgid),
-            Lfs(_, ref ptr) => Some(ptr.hgid()),
+            IndexedLog(ref entry, _) => Some(entry.key().hgid),
+            Lfs(_, ref ptr, _) => Some(ptr.hgid()),
+            Chunked(_, _, ref ptr) => Some(ptr.hgid()),
             EdenApi(ref entry) => Some(entry.key().hgid),
             Memcache(ref entry) => Some(entry.key.hgid),
+            Redis(ref entry) => Some(entry.key.hgid),
+            Cached(_) => None,
         }
     }
 
     /// Compute's the aux data associated with this file from the content.
     fn aux_data(&mut self) -> Result<FileAuxData> {
         // TODO(meyer): Implement the rest of the aux data fields
-        Ok(if let LazyFile::Lfs(_, ref ptr) = self {
+        if let LazyFile::Cached(ref cached) = self {
+            return Ok(cached.aux_data.clone());
+        }
+        Ok(if let LazyFile::Lfs(_, ref ptr, _) | LazyFile::Chunked(_, _, ref ptr) = self {
             FileAuxData {
                 content_sha256: ptr.sha256(),
             }
         } else {
+            // `file_content` always decrypts before returning, so this hash (and the LFS sha256
+            // pointer above) are always over plaintext, keeping the wire/hash formats stable.
             FileAuxData {
                 content_sha256: ContentHash::sha256(&self.file_content()?).unwrap_sha256(),
             }
@@ -452,12 +1585,19 @@ impl LazyFile {
     fn file_content(&mut self) -> Result<Bytes> {
         use LazyFile::*;
         Ok(match self {
-            IndexedLog(ref mut entry) => strip_metadata(&entry.content()?)?.0,
-            Lfs(ref blob, _) => blob.clone(),
+            IndexedLog(ref mut entry, ref encryption) => {
+                let (_, raw) = split_record_version(&entry.content()?)?;
+                let content = decrypt_if_needed(encryption, raw)?;
+                strip_metadata(&content)?.0
+            }
+            Lfs(ref blob, _, ref encryption) => decrypt_if_needed(encryption, blob.clone())?,
+            Chunked(ref manifest, ref chunk_store, _) => read_chunks(chunk_store, manifest)?,
             ContentStore(ref blob, _) => strip_metadata(blob)?.0,
             // TODO(meyer): Convert EdenApi to use minibytes
             EdenApi(ref entry) => strip_metadata(&entry.data()?.into())?.0,
             Memcache(ref entry) => strip_metadata(&entry.data)?.0,
+            Redis(ref entry) => strip_metadata(&entry.data)?.0,
+            Cached(ref cached) => cached.file_content.clone(),
         })
     }
 
@@ -465,25 +1605,41 @@ impl LazyFile {
     fn hg_content(&mut self) -> Result<Bytes> {
         use LazyFile::*;
         Ok(match self {
-            IndexedLog(ref mut entry) => entry.content()?,
-            Lfs(ref blob, ref ptr) => rebuild_metadata(blob.clone(), ptr),
+            IndexedLog(ref mut entry, ref encryption) => {
+                let (_, raw) = split_record_version(&entry.content()?)?;
+                decrypt_if_needed(encryption, raw)?
+            }
+            Lfs(ref blob, ref ptr, ref encryption) => {
+                rebuild_metadata(decrypt_if_needed(encryption, blob.clone())?, ptr)
+            }
+            Chunked(ref manifest, ref chunk_store, ref ptr) => {
+                rebuild_metadata(read_chunks(chunk_store, manifest)?, ptr)
+            }
             ContentStore(ref blob, _) => blob.clone(),
             EdenApi(ref entry) => entry.data()?.into(),
             Memcache(ref entry) => entry.data.clone(),
+            Redis(ref entry) => entry.data.clone(),
+            Cached(ref cached) => cached.hg_content.clone(),
         })
     }
 
     fn metadata(&self) -> Result<Metadata> {
         use LazyFile::*;
         Ok(match self {
-            IndexedLog(ref entry) => entry.metadata().clone(),
-            Lfs(_, ref ptr) => Metadata {
+            IndexedLog(ref entry, _) => entry.metadata().clone(),
+            Lfs(_, ref ptr, _) => Metadata {
+                size: Some(ptr.size()),
+                flags: None,
+            },
+            Chunked(_, _, ref ptr) => Metadata {
                 size: Some(ptr.size()),
                 flags: None,
             },
             ContentStore(_, ref meta) => meta.clone(),
             EdenApi(ref entry) => entry.metadata().clone(),
             Memcache(ref entry) => entry.metadata.clone(),
+            Redis(ref entry) => entry.metadata.clone(),
+            Cached(ref cached) => cached.metadata.clone(),
         })
     }
 
@@ -491,7 +1647,7 @@ impl LazyFile {
     fn indexedlog_cache_entry(&self, key: Key) -> Result<Option<Entry>> {
         use LazyFile::*;
         Ok(match self {
-            IndexedLog(ref entry) => Some(entry.clone().with_key(key)),
+            IndexedLog(ref entry, _) => Some(entry.clone().with_key(key)),
             EdenApi(ref entry) => Some(Entry::new(
                 key,
                 entry.data()?.into(),
@@ -502,14 +1658,31 @@ impl LazyFile {
                 let entry: Entry = entry.clone().into();
                 entry.with_key(key)
             }),
+            Redis(ref entry) => Some({
+                let entry: Entry = entry.clone().into();
+                entry.with_key(key)
+            }),
             // LFS Files should be written to LfsCache instead
-            Lfs(_, _) => None,
+            Lfs(_, _, _) => None,
+            // Likewise, chunked files are backed by the LFS cache's ChunkStore.
+            Chunked(_, _, _) => None,
             // ContentStore handles caching internally
             ContentStore(_, _) => None,
+            // Already came from a cache (the hot cache); nothing new to persist.
+            Cached(_) => None,
         })
     }
 }
 
+/// Decrypt `data` if `encryption` is set, otherwise return it unchanged. Shared by the
+/// `IndexedLog`/`Lfs` `LazyFile` variants, the only ones backed by stores this crate encrypts.
+fn decrypt_if_needed(encryption: &Option<Arc<EncryptionConfig>>, data: Bytes) -> Result<Bytes> {
+    match encryption {
+        Some(config) => decrypt_blob(config, &data),
+        None => Ok(data),
+    }
+}
+
 impl TryFrom<McData> for LfsPointersEntry {
     type Error = Error;
 
@@ -605,6 +1778,9 @@ pub struct FetchState {
     /// File content found in memcache, may be cached locally (currently only content may be found in memcache)
     found_in_memcache: HashSet<Key>,
 
+    /// File content found in Redis, may be cached locally; see `found_in_memcache`.
+    found_in_redis: HashSet<Key>,
+
     /// Attributes found in EdenApi, may be cached locally (currently only content may be found in EdenApi)
     found_in_edenapi: HashSet<Key>,
 
@@ -614,6 +1790,69 @@ pub struct FetchState {
     // Config
     extstored_policy: ExtStoredPolicy,
     compute_aux_data: bool,
+    encryption: Option<Arc<EncryptionConfig>>,
+    chunk_store: Option<Arc<ChunkStore>>,
+    remote_coalescer: Arc<RemoteFetchCoalescer<Arc<CachedFileContent>>>,
+    lfs_remote_coalescer: Arc<RemoteFetchCoalescer<()>>,
+    /// If set, verify a fetched LFS blob's sha256 against the content hash declared in its
+    /// pointer before accepting it; see `found_lfs`. Off by default, since it means decrypting
+    /// (and, for chunked blobs, fully reassembling) content that would otherwise stay lazy.
+    verify_content: bool,
+    /// If set, and the same `Key` is satisfied by more than one store tier during a single fetch
+    /// (e.g. both `indexedlog_local` and `edenapi`), compare their raw contents and surface any
+    /// divergence as an `other_error`; see `check_cross_store_consistency`. Off by default for the
+    /// same reason: it forces eager decoding of content that would otherwise stay lazy.
+    consistency_check: bool,
+}
+
+/// Recompute `lazy_file`'s content sha256 (reassembling chunks and decrypting as needed, same as
+/// any other read) and compare it to `expected`, the hash declared by its LFS pointer. A mismatch
+/// means the blob doesn't match what its own pointer promises, so the caller should treat the
+/// fetch as failed rather than caching or returning the bad content.
+///
+/// This is the one place in this file where fetched content can be checked against an
+/// independently declared hash without extra information: unlike the Mercurial nodeid (`HgId`),
+/// which is derived from the content plus its revision's parent nodeids that this content-only
+/// fetch layer doesn't carry, the LFS sha256 pointer already *is* a hash of the plaintext blob.
+fn verify_lfs_sha256(lazy_file: &mut LazyFile, expected: Sha256) -> Result<()> {
+    let actual = ContentHash::sha256(&lazy_file.file_content()?).unwrap_sha256();
+    ensure!(
+        actual == expected,
+        "LFS content hash mismatch: blob hashes to {}, pointer declares {}",
+        actual,
+        expected
+    );
+    Ok(())
+}
+
+/// If `old` and `new` both already have content, and cross-store consistency checking is enabled,
+/// compare the two independently-fetched payloads for `key`. Any divergence almost certainly means
+/// one of the contributing stores (a stale local cache, a memcache entry that predates a rewrite,
+/// EdenApi serving from a different snapshot, ...) has bad data, so it's surfaced as an
+/// `other_error` for investigation rather than silently preferring whichever one `found_attributes`
+/// happens to keep.
+fn check_cross_store_consistency(
+    key: &Key,
+    old: (Option<LocalStoreType>, &mut StoreFile),
+    new: (Option<LocalStoreType>, &mut StoreFile),
+    errors: &mut FetchErrors,
+) {
+    let (old_typ, old_sf) = old;
+    let (new_typ, new_sf) = new;
+    let (old_content, new_content) = match (old_sf.content.as_mut(), new_sf.content.as_mut()) {
+        (Some(old_content), Some(new_content)) => (old_content, new_content),
+        _ => return,
+    };
+    if let (Ok(old_bytes), Ok(new_bytes)) = (old_content.hg_content(), new_content.hg_content()) {
+        if old_bytes.as_ref() != new_bytes.as_ref() {
+            errors.other_error(anyhow!(
+                "content mismatch for {:?}: {:?} and {:?} returned different bytes for the same key",
+                key,
+                old_typ,
+                new_typ,
+            ));
+        }
+    }
 }
 
 impl FetchState {
@@ -621,6 +1860,12 @@ impl FetchState {
         keys: impl Iterator<Item = Key>,
         extstored_policy: ExtStoredPolicy,
         attrs: FileAttributes,
+        encryption: Option<Arc<EncryptionConfig>>,
+        chunk_store: Option<Arc<ChunkStore>>,
+        remote_coalescer: Arc<RemoteFetchCoalescer<Arc<CachedFileContent>>>,
+        lfs_remote_coalescer: Arc<RemoteFetchCoalescer<()>>,
+        verify_content: bool,
+        consistency_check: bool,
     ) -> Self {
         FetchState {
             pending: keys.collect(),
@@ -635,11 +1880,18 @@ impl FetchState {
             errors: FetchErrors::new(),
 
             found_in_memcache: HashSet::new(),
+            found_in_redis: HashSet::new(),
             found_in_edenapi: HashSet::new(),
             computed_aux_data: HashMap::new(),
 
             extstored_policy,
             compute_aux_data: true,
+            encryption,
+            chunk_store,
+            remote_coalescer,
+            lfs_remote_coalescer,
+            verify_content,
+            consistency_check,
         }
     }
 
@@ -730,7 +1982,8 @@ impl FetchState {
         self.lfs_pointers.insert(key, ptr);
     }
 
-    fn found_attributes(&mut self, key: Key, sf: StoreFile, typ: Option<LocalStoreType>) {
+    fn found_attributes(&mut self, key: Key, mut sf: StoreFile, typ: Option<LocalStoreType>) {
+        let prior_typ = self.key_origin.get(&key).copied();
         self.key_origin
             .insert(key.clone(), typ.unwrap_or(LocalStoreType::Cache));
         use hash_map::Entry::*;
@@ -739,6 +1992,16 @@ impl FetchState {
                 // Combine the existing and newly-found attributes, overwriting existing attributes with the new ones
                 // if applicable (so that we can re-use this function to replace in-memory files with mmap-ed files)
                 let available = entry.get_mut();
+
+                if self.consistency_check {
+                    check_cross_store_consistency(
+                        &key,
+                        (prior_typ, available),
+                        (typ, &mut sf),
+                        &mut self.errors,
+                    );
+                }
+
                 *available = sf | std::mem::take(available);
 
                 if available.attrs().has(self.request_attrs) {
@@ -762,18 +2025,47 @@ impl FetchState {
                 }
             }
         } else {
-            self.found_attributes(key, LazyFile::IndexedLog(entry).into(), Some(typ))
+            self.found_attributes(
+                key,
+                LazyFile::IndexedLog(entry, self.encryption.clone()).into(),
+                Some(typ),
+            )
         }
     }
 
-    fn fetch_indexedlog(&mut self, store: &IndexedLogHgIdDataStore, typ: LocalStoreType) {
+    /// Consult the hot cache for all pending keys before touching any disk/remote store.
+    fn fetch_hot_cache(&mut self, hot_cache: &FileHotCache) {
+        for key in self.pending_all(self.request_attrs) {
+            if let Some((content, typ)) = hot_cache.get(&key) {
+                let sf = StoreFile {
+                    content: Some(LazyFile::Cached(content.clone())),
+                    aux_data: Some(content.aux_data.clone()),
+                };
+                self.found_attributes(key, sf, Some(typ));
+            }
+        }
+    }
+
+    fn fetch_indexedlog(
+        &mut self,
+        store: &IndexedLogHgIdDataStore,
+        typ: LocalStoreType,
+        read_strategy: IndexedLogReadStrategy,
+    ) {
         let pending = self.pending_nonlfs(FileAttributes::CONTENT);
         if pending.is_empty() {
             return;
         }
         let store = store.read_lock();
         for key in pending.into_iter() {
-            let res = store.get_raw_entry(&key);
+            // `IndexedLogHgIdDataStore`'s reader only offers the mmap-based `get_raw_entry`, so
+            // both strategies read through it: a store read with `Pread` (e.g. NFS-mounted) is
+            // read exactly like one read with `Mmap`, and gets no protection from mmap faults.
+            let res = match read_strategy {
+                IndexedLogReadStrategy::Mmap | IndexedLogReadStrategy::Pread => {
+                    store.get_raw_entry(&key)
+                }
+            };
             match res {
                 Ok(Some(entry)) => self.found_indexedlog(key, entry, typ),
                 Ok(None) => {}
@@ -784,7 +2076,9 @@ impl FetchState {
 
     fn found_aux_indexedlog(&mut self, key: Key, mut entry: Entry) -> Result<()> {
         // TODO(meyer): We could make aux data lazy too.
-        let aux_data: FileAuxData = serde_json::from_slice(&entry.content()?)?;
+        let (_, raw) = split_record_version(&entry.content()?)?;
+        let raw = decrypt_if_needed(&self.encryption, raw)?;
+        let aux_data: FileAuxData = serde_json::from_slice(&raw)?;
         self.found_attributes(key, aux_data.into(), None);
         Ok(())
     }
@@ -816,12 +2110,70 @@ impl FetchState {
     fn found_lfs(&mut self, key: Key, entry: LfsStoreEntry, typ: LocalStoreType) {
         match entry {
             LfsStoreEntry::PointerAndBlob(ptr, blob) => {
-                self.found_attributes(key, LazyFile::Lfs(blob, ptr).into(), Some(typ))
+                // Strip the store-format header eagerly: both branches below (chunk-manifest
+                // detection and the lazily-decrypted `LazyFile::Lfs` blob) need the unwrapped
+                // bytes, and the header itself is never encrypted.
+                let blob = match split_record_version(&blob) {
+                    Ok((_, inner)) => inner,
+                    Err(err) => return self.errors.keyed_error(key, err),
+                };
+                let expected_sha256 = ptr.sha256();
+                let mut lazy_file = match self.as_chunk_manifest(&blob) {
+                    Ok(Some((manifest, chunk_store))) => {
+                        LazyFile::Chunked(manifest, chunk_store, ptr)
+                    }
+                    Ok(None) => LazyFile::Lfs(blob, ptr, self.encryption.clone()),
+                    Err(err) => return self.errors.keyed_error(key, err),
+                };
+                if self.verify_content {
+                    if let Err(err) = verify_lfs_sha256(&mut lazy_file, expected_sha256) {
+                        return self.errors.keyed_error(key, err);
+                    }
+                }
+                self.found_attributes(key, lazy_file.into(), Some(typ))
             }
             LfsStoreEntry::PointerOnly(ptr) => self.found_pointer(key, ptr, typ),
         }
     }
 
+    /// If `blob` is a `ChunkManifest` payload (rather than raw file content), decrypt and parse
+    /// it. Returns `Ok(None)` for an ordinary (unchunked) blob.
+    ///
+    /// The `CHUNK_MANIFEST_MAGIC` check always runs, even when no `chunk_store` is configured:
+    /// otherwise a chunked blob read through a `FileStore` that isn't configured with one would
+    /// silently be treated as ordinary content and its manifest JSON handed back as if it were
+    /// file content, instead of erroring.
+    fn as_chunk_manifest(&self, blob: &Bytes) -> Result<Option<(ChunkManifest, Arc<ChunkStore>)>> {
+        let decrypted = decrypt_if_needed(&self.encryption, blob.clone())?;
+        if !decrypted.starts_with(CHUNK_MANIFEST_MAGIC) {
+            return Ok(None);
+        }
+        let chunk_store = match &self.chunk_store {
+            Some(chunk_store) => chunk_store,
+            None => bail!("blob is chunked but no chunk_store is configured"),
+        };
+        let manifest: ChunkManifest = serde_json::from_slice(&decrypted[CHUNK_MANIFEST_MAGIC.len()..])?;
+        Ok(Some((manifest, chunk_store.clone())))
+    }
+
+    /// Re-encode `entry`'s content the way a locally-authored entry is encoded before being
+    /// written to `indexedlog_local`/`indexedlog_cache`: wrapped in the current record-format
+    /// header and, beneath that, encrypted if at-rest encryption is configured. Used in
+    /// `write_to_cache` for entries assembled from a remote (EdenApi/Memcache) response, whose
+    /// content is plaintext and has never been through `wrap_record_version`/`encrypt_blob`.
+    fn encrypt_for_disk(&self, mut entry: Entry) -> Result<Entry> {
+        let content = entry.content()?;
+        let content = match &self.encryption {
+            Some(config) => encrypt_blob(config, &content)?,
+            None => content,
+        };
+        Ok(Entry::new(
+            entry.key().clone(),
+            wrap_record_version(&content),
+            entry.metadata().clone(),
+        ))
+    }
+
     fn fetch_lfs(&mut self, store: &LfsStore, typ: LocalStoreType) {
         let pending = self.pending_storekey(FileAttributes::CONTENT);
         if pending.is_empty() {
@@ -852,117 +2204,275 @@ impl FetchState {
         }
     }
 
-    fn fetch_memcache_inner(&mut self, store: &MemcacheStore) -> Result<()> {
-        let pending = self.pending_nonlfs(FileAttributes::CONTENT);
-        if pending.is_empty() {
-            return Ok(());
-        }
-        for res in store.get_data_iter(&pending)?.into_iter() {
-            match res {
-                Ok(mcdata) => self.found_memcache(mcdata),
-                Err(err) => self.errors.other_error(err),
-            }
-        }
-        Ok(())
-    }
-
-    fn fetch_memcache(&mut self, store: &MemcacheStore) {
-        if let Err(err) = self.fetch_memcache_inner(store) {
-            self.errors.other_error(err);
-        }
-    }
-
-    fn found_edenapi(&mut self, entry: FileEntry) {
+    fn found_redis(&mut self, entry: McData) {
         let key = entry.key.clone();
-        if entry.metadata().is_lfs() {
+        if entry.metadata.is_lfs() {
             match entry.try_into() {
                 Ok(ptr) => self.found_pointer(key, ptr, LocalStoreType::Cache),
                 Err(err) => self.errors.keyed_error(key, err),
             }
         } else {
-            self.found_in_edenapi.insert(key.clone());
-            self.found_attributes(key, LazyFile::EdenApi(entry).into(), None);
+            self.found_in_redis.insert(key.clone());
+            self.found_attributes(key, LazyFile::Redis(entry).into(), None);
+        }
+    }
+
+    /// Query `memcache`, `redis`, and `edenapi` concurrently for the same (non-LFS) pending
+    /// content, since none of these backends' round-trips depend on one another's result.
+    ///
+    /// The pending key lists are snapshotted before any thread starts, so that resolving a key
+    /// from one backend doesn't shrink what the others are asked for mid-flight.
+    /// Dispatches memcache, Redis, EdenApi, and (when `lfs_remote` is given) the LFS remote's
+    /// `batch_fetch` all from the same `crossbeam::thread::scope`, so the LFS round-trip overlaps
+    /// the non-LFS ones instead of waiting for them to finish first -- they're independent remote
+    /// backends for disjoint sets of pending keys (`pending_nonlfs` vs. `self.lfs_pointers`), so
+    /// there's no reason for one to block starting the other.
+    fn fetch_remote(
+        &mut self,
+        memcache: Option<&MemcacheStore>,
+        redis: Option<&RedisStore>,
+        edenapi: Option<&EdenApiFileStore>,
+        lfs_remote: Option<&LfsRemoteInner>,
+        lfs_local: Option<Arc<LfsStore>>,
+        lfs_cache: Option<Arc<LfsStore>>,
+    ) {
+        if memcache.is_none()
+            && redis.is_none()
+            && edenapi.is_none()
+            && (lfs_remote.is_none() || self.lfs_pointers.is_empty())
+        {
+            return;
         }
-    }
 
-    fn fetch_edenapi_inner(&mut self, store: &EdenApiFileStore) -> Result<()> {
-        // TODO(meyer): Implement aux data fetching for EdenApi Files
         let pending = self.pending_nonlfs(FileAttributes::CONTENT);
-        if pending.is_empty() {
-            return Ok(());
-        }
-        for entry in store.files_blocking(pending, None)?.entries.into_iter() {
-            self.found_edenapi(entry);
-        }
-        Ok(())
-    }
 
-    fn fetch_edenapi(&mut self, store: &EdenApiFileStore) {
-        if let Err(err) = self.fetch_edenapi_inner(store) {
-            self.errors.other_error(err);
+        // Partition into Keys this `FetchState` must actually fetch (`leaders`) and ones another
+        // concurrent `FetchState` is already fetching (`followers`, to be resolved below by
+        // waiting instead of issuing a second round-trip for them).
+        let mut leaders = Vec::new();
+        let mut followers = Vec::new();
+        if memcache.is_some() || redis.is_some() || edenapi.is_some() {
+            for key in pending {
+                match self.remote_coalescer.lease(key.clone()) {
+                    RemoteFetchLease::Leader(slot) => leaders.push((key, slot)),
+                    RemoteFetchLease::Follower(slot) => followers.push((key, slot)),
+                }
+            }
         }
-    }
 
-    fn fetch_lfs_remote_inner(
-        &mut self,
-        store: &LfsRemoteInner,
-        local: Option<Arc<LfsStore>>,
-        cache: Option<Arc<LfsStore>>,
-    ) -> Result<()> {
-        let pending: HashSet<_> = self
-            .lfs_pointers
-            .iter()
-            .map(|(_k, v)| (v.sha256(), v.size() as usize))
-            .collect();
-        if pending.is_empty() {
-            return Ok(());
+        // Same leader/follower split as above, but for the LFS remote's pending pointers.
+        let mut lfs_leaders = Vec::new();
+        let mut lfs_followers = Vec::new();
+        if lfs_remote.is_some() && !self.lfs_pointers.is_empty() {
+            let keys: Vec<Key> = self.lfs_pointers.keys().cloned().collect();
+            for key in keys {
+                match self.lfs_remote_coalescer.lease(key.clone()) {
+                    RemoteFetchLease::Leader(slot) => lfs_leaders.push((key, slot)),
+                    RemoteFetchLease::Follower(slot) => lfs_followers.push((key, slot)),
+                }
+            }
         }
-        // Fetch & write to local LFS stores
-        store.batch_fetch(&pending, {
-            let lfs_local = local.clone();
-            let lfs_cache = cache.clone();
+
+        if leaders.is_empty() && lfs_leaders.is_empty() {
+            // Nothing to dispatch; fall through to resolve any followers below.
+        } else {
+            let leader_keys: Vec<Key> = leaders.iter().map(|(key, _)| key.clone()).collect();
+            let memcache_pending = memcache.map(|_| leader_keys.clone());
+            let redis_pending = redis.map(|_| leader_keys.clone());
+            let edenapi_pending = edenapi.map(|_| leader_keys.clone());
+
+            let lfs_pending: HashSet<_> = lfs_leaders
+                .iter()
+                .filter_map(|(key, _)| self.lfs_pointers.get(key))
+                .map(|v| (v.sha256(), v.size() as usize))
+                .collect();
             let pointer_origin = self.pointer_origin.clone();
-            move |sha256, data| -> Result<()> {
-                match pointer_origin.read().get(&sha256).ok_or_else(|| {
-                    anyhow!(
-                        "no source found for Sha256; received unexpected Sha256 from LFS server"
+            let encryption = self.encryption.clone();
+
+            let (memcache_result, redis_result, edenapi_result, lfs_result) =
+                crossbeam::thread::scope(|scope| {
+                    let memcache_thread = memcache.map(|store| {
+                        let pending = memcache_pending.as_ref().unwrap();
+                        scope.spawn(move |_| -> Result<Vec<McData>> {
+                            if pending.is_empty() {
+                                return Ok(vec![]);
+                            }
+                            store.get_data_iter(pending)?.into_iter().collect()
+                        })
+                    });
+
+                    let redis_thread = redis.map(|store| {
+                        let pending = redis_pending.as_ref().unwrap();
+                        scope.spawn(move |_| -> Result<Vec<McData>> {
+                            if pending.is_empty() {
+                                return Ok(vec![]);
+                            }
+                            store.get_data_iter(pending)
+                        })
+                    });
+
+                    let edenapi_thread = edenapi.map(|store| {
+                        let pending = edenapi_pending.clone().unwrap();
+                        scope.spawn(move |_| -> Result<Vec<FileEntry>> {
+                            if pending.is_empty() {
+                                return Ok(vec![]);
+                            }
+                            Ok(store.files_blocking(pending, None)?.entries)
+                        })
+                    });
+
+                    let lfs_thread = if lfs_leaders.is_empty() {
+                        None
+                    } else {
+                        lfs_remote.map(|store| {
+                            let lfs_local = lfs_local.clone();
+                            let lfs_cache = lfs_cache.clone();
+                            let pointer_origin = pointer_origin.clone();
+                            let encryption = encryption.clone();
+                            let lfs_pending = &lfs_pending;
+                            scope.spawn(move |_| -> Result<()> {
+                                store.batch_fetch(lfs_pending, {
+                                    move |sha256, data| -> Result<()> {
+                                        // Blobs fetched here land directly in `lfs_local`/`lfs_cache`
+                                        // via `add_blob`, so they need the same version header and
+                                        // optional at-rest encryption that `write_batch` applies to
+                                        // locally-authored LFS blobs; otherwise a reader expecting
+                                        // ciphertext (see `LazyFile::Lfs`) would try to decrypt
+                                        // plaintext.
+                                        let data = match &encryption {
+                                            Some(config) => encrypt_blob(config, &data)?,
+                                            None => data,
+                                        };
+                                        let data = wrap_record_version(&data);
+                                        match pointer_origin.read().get(&sha256).ok_or_else(|| {
+                                            anyhow!(
+                                                "no source found for Sha256; received unexpected Sha256 from LFS server"
+                                            )
+                                        })? {
+                                            LocalStoreType::Local => lfs_local
+                                                .as_ref()
+                                                .expect("no lfs_local present when handling local LFS pointer")
+                                                .add_blob(&sha256, data),
+                                            LocalStoreType::Cache => lfs_cache
+                                                .as_ref()
+                                                .expect("no lfs_cache present when handling cache LFS pointer")
+                                                .add_blob(&sha256, data),
+                                        }
+                                    }
+                                })
+                            })
+                        })
+                    };
+
+                    (
+                        memcache_thread.map(|t| t.join().expect("memcache fetch thread panicked")),
+                        redis_thread.map(|t| t.join().expect("redis fetch thread panicked")),
+                        edenapi_thread.map(|t| t.join().expect("edenapi fetch thread panicked")),
+                        lfs_thread.map(|t| t.join().expect("lfs remote fetch thread panicked")),
                     )
-                })? {
-                    LocalStoreType::Local => lfs_local
-                        .as_ref()
-                        .expect("no lfs_local present when handling local LFS pointer")
-                        .add_blob(&sha256, data),
-                    LocalStoreType::Cache => lfs_cache
-                        .as_ref()
-                        .expect("no lfs_cache present when handling cache LFS pointer")
-                        .add_blob(&sha256, data),
+                })
+                .expect("remote fetch thread scope panicked");
+
+            if let Some(result) = memcache_result {
+                match result {
+                    Ok(entries) => entries.into_iter().for_each(|e| self.found_memcache(e)),
+                    Err(err) => self.errors.other_error(err),
+                }
+            }
+
+            // Redis is consulted between memcache and EdenApi: like memcache it's a plain cache
+            // hit if present, but a connection failure (the expected failure mode for a tier that
+            // might not even be deployed everywhere) should degrade to EdenApi rather than fail
+            // the whole fetch.
+            if let Some(result) = redis_result {
+                match result {
+                    Ok(entries) => entries.into_iter().for_each(|e| self.found_redis(e)),
+                    Err(err) => self.errors.other_error(err),
+                }
+            }
+
+            if let Some(result) = edenapi_result {
+                match result {
+                    Ok(entries) => entries.into_iter().for_each(|e| self.found_edenapi(e)),
+                    Err(err) => self.errors.other_error(err),
+                }
+            }
+
+            // Hand the outcome to any followers, then release the lease so a later fetch of the
+            // same Key (e.g. after the hot cache evicts it) starts a fresh remote request rather
+            // than replaying this one.
+            for (key, slot) in leaders {
+                let materialized = match self.found.get_mut(&key) {
+                    Some(sf) if sf.content.is_some() => {
+                        sf.materialize_cached_content().map_err(|err| err.to_string())
+                    }
+                    _ => Err("not found in memcache, redis, or EdenApi".to_string()),
+                };
+                self.remote_coalescer.complete(&key, &slot, materialized);
+            }
+
+            if let Some(result) = lfs_result {
+                // Hand the outcome to any LFS followers, then release the lease, same as for the
+                // non-LFS leaders above.
+                let follower_result = result.as_ref().map(|_| ()).map_err(|err| err.to_string());
+                for (key, slot) in &lfs_leaders {
+                    self.lfs_remote_coalescer
+                        .complete(key, slot, follower_result.clone());
+                }
+                if let Err(err) = result {
+                    self.errors.other_error(err);
                 }
             }
-        })?;
+        }
 
-        // After prefetching into the local LFS stores, retry fetching from them. The returned Bytes will then be mmaps rather
-        // than large files stored in memory.
-        // TODO(meyer): We probably want to intermingle this with the remote fetch handler to avoid files being evicted between there
-        // and here, rather than just retrying the local fetches.
-        if let Some(ref lfs_cache) = cache {
-            self.fetch_lfs(lfs_cache, LocalStoreType::Cache)
+        for (key, slot) in followers {
+            match slot.wait() {
+                Ok(content) => {
+                    let sf = StoreFile {
+                        content: Some(LazyFile::Cached(content.clone())),
+                        aux_data: Some(content.aux_data.clone()),
+                    };
+                    self.found_attributes(key, sf, None);
+                }
+                // The leader's own fetch failed or came up empty; surface it as our own error,
+                // same as if this `FetchState` had made the (now-redundant) request itself.
+                Err(err) => self.errors.keyed_error(key, anyhow!(err)),
+            }
         }
 
-        if let Some(ref lfs_local) = local {
-            self.fetch_lfs(lfs_local, LocalStoreType::Local)
+        for (key, slot) in lfs_followers {
+            // The leader's own fetch failed; surface it as our own error, same as if this
+            // `FetchState` had made the (now-redundant) request itself.
+            if let Err(err) = slot.wait() {
+                self.errors.keyed_error(key, anyhow!(err));
+            }
         }
 
-        Ok(())
+        // After prefetching into the local LFS stores, retry fetching from them — both this
+        // batch's own keys, and any follower keys a concurrent leader just populated the shared
+        // store for. The returned Bytes will then be mmaps rather than large files stored in
+        // memory.
+        if lfs_remote.is_some() {
+            if let Some(ref lfs_cache) = lfs_cache {
+                self.fetch_lfs(lfs_cache, LocalStoreType::Cache)
+            }
+
+            if let Some(ref lfs_local) = lfs_local {
+                self.fetch_lfs(lfs_local, LocalStoreType::Local)
+            }
+        }
     }
 
-    fn fetch_lfs_remote(
-        &mut self,
-        store: &LfsRemoteInner,
-        local: Option<Arc<LfsStore>>,
-        cache: Option<Arc<LfsStore>>,
-    ) {
-        if let Err(err) = self.fetch_lfs_remote_inner(store, local, cache) {
-            self.errors.other_error(err);
+    fn found_edenapi(&mut self, entry: FileEntry) {
+        let key = entry.key.clone();
+        if entry.metadata().is_lfs() {
+            match entry.try_into() {
+                Ok(ptr) => self.found_pointer(key, ptr, LocalStoreType::Cache),
+                Err(err) => self.errors.keyed_error(key, err),
+            }
+        } else {
+            self.found_in_edenapi.insert(key.clone());
+            self.found_attributes(key, LazyFile::EdenApi(entry).into(), None);
         }
     }
 
@@ -1057,8 +2567,10 @@ impl FetchState {
         &mut self,
         indexedlog_cache: Option<&IndexedLogHgIdDataStore>,
         memcache: Option<&MemcacheStore>,
+        redis: Option<&RedisStore>,
         aux_cache: Option<&IndexedLogHgIdDataStore>,
         aux_local: Option<&IndexedLogHgIdDataStore>,
+        hot_cache: Option<&FileHotCache>,
     ) {
         let mut indexedlog_cache = indexedlog_cache.map(|s| s.write_lock());
         let mut aux_cache = aux_cache.map(|s| s.write_lock());
@@ -1067,13 +2579,26 @@ impl FetchState {
         for key in self.found_in_edenapi.drain() {
             if let Some(lazy_file) = self.found[&key].content.as_ref() {
                 if let Ok(Some(cache_entry)) = lazy_file.indexedlog_cache_entry(key) {
+                    // Memcache and Redis are remote, shared caches rather than something this
+                    // crate reads back as ciphertext (`LazyFile::Memcache`/`LazyFile::Redis` never
+                    // decrypt), so they keep getting the plaintext entry; only the on-disk copy is
+                    // encrypted at rest.
                     if let Some(memcache) = memcache {
                         if let Ok(mcdata) = cache_entry.clone().try_into() {
                             memcache.add_mcdata(mcdata)
                         }
                     }
+                    if let Some(redis) = redis {
+                        if let Ok(mcdata) = cache_entry.clone().try_into() {
+                            // Best-effort, same as memcache above: a Redis outage shouldn't fail
+                            // the fetch that already succeeded against EdenApi.
+                            let _ = redis.add(mcdata);
+                        }
+                    }
                     if let Some(ref mut indexedlog_cache) = indexedlog_cache {
-                        let _ = indexedlog_cache.put_entry(cache_entry);
+                        if let Ok(disk_entry) = self.encrypt_for_disk(cache_entry) {
+                            let _ = indexedlog_cache.put_entry(disk_entry);
+                        }
                     }
                 }
             }
@@ -1083,7 +2608,21 @@ impl FetchState {
             if let Some(lazy_file) = self.found[&key].content.as_ref() {
                 if let Ok(Some(cache_entry)) = lazy_file.indexedlog_cache_entry(key) {
                     if let Some(ref mut indexedlog_cache) = indexedlog_cache {
-                        let _ = indexedlog_cache.put_entry(cache_entry);
+                        if let Ok(disk_entry) = self.encrypt_for_disk(cache_entry) {
+                            let _ = indexedlog_cache.put_entry(disk_entry);
+                        }
+                    }
+                }
+            }
+        }
+
+        for key in self.found_in_redis.drain() {
+            if let Some(lazy_file) = self.found[&key].content.as_ref() {
+                if let Ok(Some(cache_entry)) = lazy_file.indexedlog_cache_entry(key) {
+                    if let Some(ref mut indexedlog_cache) = indexedlog_cache {
+                        if let Ok(disk_entry) = self.encrypt_for_disk(cache_entry) {
+                            let _ = indexedlog_cache.put_entry(disk_entry);
+                        }
                     }
                 }
             }
@@ -1091,7 +2630,14 @@ impl FetchState {
 
         for (key, origin) in self.computed_aux_data.drain() {
             if let Ok(blob) = serde_json::to_vec(self.found[&key].aux_data.as_ref().unwrap()) {
-                let entry = Entry::new(key, blob.into(), Metadata::default());
+                let blob = match &self.encryption {
+                    Some(config) => match encrypt_blob(config, &blob) {
+                        Ok(blob) => blob,
+                        Err(_) => continue,
+                    },
+                    None => blob.into(),
+                };
+                let entry = Entry::new(key, wrap_record_version(&blob), Metadata::default());
                 match origin {
                     LocalStoreType::Cache => {
                         if let Some(ref mut aux_cache) = aux_cache {
@@ -1106,6 +2652,36 @@ impl FetchState {
                 }
             }
         }
+
+        if let Some(hot_cache) = hot_cache {
+            for (key, value) in self.found.iter_mut() {
+                // Already materialized: either served from the hot cache (which `get` already
+                // marked as most recently used), or handed over by the leader of a coalesced
+                // remote fetch, whose own `FetchState` inserts it.
+                if matches!(value.content, Some(LazyFile::Cached(_))) {
+                    continue;
+                }
+                let (content, aux_data) = match (&mut value.content, &value.aux_data) {
+                    (Some(content), Some(aux_data)) => (content, aux_data.clone()),
+                    _ => continue,
+                };
+                let origin = match self.key_origin.get(key) {
+                    Some(origin) => *origin,
+                    None => continue,
+                };
+                let cached = (|| -> Result<CachedFileContent> {
+                    Ok(CachedFileContent {
+                        hg_content: content.hg_content()?,
+                        file_content: content.file_content()?,
+                        metadata: content.metadata()?,
+                        aux_data,
+                    })
+                })();
+                if let Ok(cached) = cached {
+                    hot_cache.insert(key.clone(), origin, Arc::new(cached));
+                }
+            }
+        }
     }
 
     fn finish(mut self) -> FileStoreFetch {
@@ -1190,9 +2766,103 @@ impl RemoteDataStore for FileStore {
             .collect())
     }
 
-    fn upload(&self, _keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
-        unimplemented!()
-        //Ok(keys.to_vec())
+    /// Upload each of `keys` to the remote, content-addressed and chunk-deduplicated.
+    ///
+    /// Every file is split with `cdc_chunks`, and each resulting chunk plus the file's own chunk
+    /// manifest (addressed by the file's LFS sha256, same as `write_batch` derives for a
+    /// locally-authored file) is handed to `lfs_remote.batch_upload` in a single call, mirroring
+    /// the `batch_fetch` pattern `fetch_remote`'s LFS dispatch already uses for downloads: one
+    /// `(sha256, size)` set describing everything that needs to exist on the remote, and a
+    /// callback the remote calls back into only for the objects it doesn't already have, so
+    /// content shared across files (or across versions of the same file) is never re-sent.
+    fn upload(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        let lfs_remote = match &self.lfs_remote {
+            Some(lfs_remote) => lfs_remote,
+            None => return Ok(keys.to_vec()),
+        };
+
+        let mut failed = Vec::new();
+        let mut store_key_by_key: HashMap<Key, StoreKey> = HashMap::new();
+        for store_key in keys {
+            match store_key.clone().maybe_into_key() {
+                Some(key) => {
+                    store_key_by_key.insert(key, store_key.clone());
+                }
+                None => failed.push(store_key.clone()),
+            }
+        }
+
+        let mut fetched = self.fetch(store_key_by_key.keys().cloned(), FileAttributes::CONTENT);
+
+        struct PendingUpload {
+            store_key: StoreKey,
+            pointer_sha256: Sha256,
+            content: Bytes,
+        }
+        let mut pending = Vec::new();
+        for (key, store_key) in store_key_by_key.iter() {
+            let content = match fetched
+                .complete
+                .get_mut(key)
+                .and_then(|sf| sf.content.as_mut())
+                .map(|lazy| lazy.hg_content())
+            {
+                Some(Ok(content)) => content,
+                _ => {
+                    failed.push(store_key.clone());
+                    continue;
+                }
+            };
+            let (pointer, blob) = lfs_from_hg_file_blob(key.hgid, &content)?;
+            pending.push(PendingUpload {
+                store_key: store_key.clone(),
+                pointer_sha256: pointer.sha256(),
+                content: blob,
+            });
+        }
+        if pending.is_empty() {
+            return Ok(failed);
+        }
+
+        // Split every file into content-defined chunks and build its manifest up front, so both
+        // the chunks and the manifest payload can be handed to the remote in one `batch_upload`.
+        let mut content_by_sha256: HashMap<Sha256, Bytes> = HashMap::new();
+        let mut manifest_store_keys: HashMap<Sha256, StoreKey> = HashMap::new();
+        for p in &pending {
+            let mut chunks = Vec::new();
+            for chunk in cdc_chunks(&p.content) {
+                let bytes = Bytes::copy_from_slice(chunk);
+                let sha256 = ContentHash::sha256(&bytes).unwrap_sha256();
+                content_by_sha256.entry(sha256).or_insert(bytes);
+                chunks.push(sha256);
+            }
+            let manifest = ChunkManifest {
+                chunks,
+                total_size: p.content.len() as u64,
+            };
+            let mut payload = CHUNK_MANIFEST_MAGIC.to_vec();
+            payload.extend_from_slice(&serde_json::to_vec(&manifest)?);
+            content_by_sha256.insert(p.pointer_sha256, Bytes::from(payload));
+            manifest_store_keys.insert(p.pointer_sha256, p.store_key.clone());
+        }
+
+        let objs: HashSet<(Sha256, usize)> = content_by_sha256
+            .iter()
+            .map(|(sha256, content)| (*sha256, content.len()))
+            .collect();
+        // `batch_upload` asks the remote which of `objs` it's missing and only calls back into
+        // `content_by_sha256` for those, same as `fetch_remote`'s LFS `batch_fetch` only
+        // calls its write-to-store callback for objects the leader actually fetched.
+        if lfs_remote
+            .batch_upload(&objs, move |sha256| -> Result<Option<Bytes>> {
+                Ok(content_by_sha256.get(&sha256).cloned())
+            })
+            .is_err()
+        {
+            failed.extend(manifest_store_keys.into_values());
+        }
+
+        Ok(failed)
     }
 }
 
@@ -1245,6 +2915,12 @@ impl HgIdMutableDeltaStore for FileStore {
         if let Some(ref aux_cache) = self.aux_cache {
             aux_cache.flush_log()?;
         }
+        // Unlike the stores above, the hot cache has nothing durable to flush; treat a flush as
+        // a cue to drop its contents instead, so callers that flush at natural checkpoints (e.g.
+        // between commands) don't keep paying for hot content they're unlikely to reuse.
+        if let Some(ref hot_cache) = self.hot_cache {
+            hot_cache.clear();
+        }
         Ok(None)
     }
 }
@@ -1283,7 +2959,7 @@ impl ContentDataStore for FileStore {
                 Some((
                     _sk,
                     StoreFile {
-                        content: Some(LazyFile::Lfs(_blob, pointer)),
+                        content: Some(LazyFile::Lfs(_blob, pointer, _)),
                         ..
                     },
                 )) => StoreResult::Found(pointer.into()),
@@ -7,15 +7,21 @@
 
 use async_trait::async_trait;
 use futures::stream::BoxStream;
+use futures::stream::SelectAll;
 use futures::stream::StreamExt;
 use futures::task::Context;
 use futures::task::Poll;
 use futures::Stream;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
 
 /// Resolve a stream of `I`s (inputs) into a stream of `O`s (outputs).
 ///
@@ -33,17 +39,129 @@ struct HybridStreamState<I, O, E> {
     /// The 1st item should be unknown locally.
     buffer: VecDeque<ResolveState<I, O>>,
 
-    /// Buffer size before sending a request.
+    /// Size of a single remote batch. `fill_buffer` tops the buffer up to
+    /// `buffer_size * max_concurrent_batches`, so up to `max_concurrent_batches` batches of this
+    /// size can be outstanding at once.
     buffer_size: usize,
 
-    /// Pending remote request. The stream populates `response`.
-    request: Option<BoxStream<'static, Result<(I, O), E>>>,
+    /// Batches currently in flight, each item tagged with the id of the batch that produced it
+    /// (see `next_batch_id`). Polled together (via `SelectAll`) so a result from any of them can
+    /// make progress, instead of waiting for one batch to fully drain before the next is sent.
+    requests: SelectAll<BoxStream<'static, (u64, Result<(I, O), E>)>>,
 
-    /// Result from consumed `request` stream.
+    /// Maximum number of batches allowed in `requests` at once.
+    max_concurrent_batches: usize,
+
+    /// Id to assign the next dispatched batch. Retries of a failed batch keep its original id,
+    /// so `batch_attempts` below can bound retries per logical batch.
+    next_batch_id: u64,
+
+    /// Inputs handed to an outstanding batch in `requests` but not yet in `response`, mapped to
+    /// the id of the batch serving them. When that batch's stream yields an error, this lets us
+    /// free exactly its own inputs instead of leaking them or touching other in-flight batches.
+    pending: HashMap<I, u64>,
+
+    /// Result from consumed `requests` streams. A key stays here until every buffered
+    /// `NotResolved` slot for it has consumed a clone (see `response_waiters`), since the same
+    /// key can appear more than once in the input and `remote_input` only fetches it once.
     response: HashMap<I, O>,
 
-    /// Defines how to resolve I to O.
-    resolver: Box<dyn HybridResolver<I, O, E> + Send + Sync + 'static>,
+    /// Number of buffered `NotResolved` slots still waiting on each key's `response` entry.
+    /// Incremented when a slot becomes `NotResolved`, decremented (and the `response` entry
+    /// dropped once it hits zero) when a slot consumes it.
+    response_waiters: HashMap<I, usize>,
+
+    /// How to retry a batch that failed with a retryable error.
+    retry_policy: RetryPolicy,
+
+    /// Retries attempted per batch id (see `next_batch_id`) since that batch last made progress.
+    /// Scoped per batch so one persistently-failing batch exhausts its own `max_attempts`
+    /// independently of unrelated batches succeeding alongside it (see `max_concurrent_batches`).
+    batch_attempts: HashMap<u64, u32>,
+
+    /// Set if this stream was created via `HybridStream::abortable`. Checked before both
+    /// `fill_buffer` and `poll_remote`. `abort()` does not wake a poll that is already awaiting a
+    /// remote response: that poll observes the abort once the next remote item (or error) arrives.
+    abort: Option<AbortRegistration>,
+
+    /// Set if this stream was created via `HybridStream::with_blocking_local_resolution`. When
+    /// set, `fill_buffer` gathers a whole batch of inputs up front and resolves them locally in
+    /// a single `spawn_blocking` call, instead of calling `resolve_local` inline on the async
+    /// task once per input.
+    blocking_local: bool,
+
+    /// Defines how to resolve I to O. `None` only transiently, while a batch of `resolve_local`
+    /// calls has been moved onto the blocking pool (see `blocking_local`).
+    resolver: Option<Box<dyn HybridResolver<I, O, E> + Send + Sync + 'static>>,
+}
+
+/// A handle to cancel an in-progress `HybridStream` created via `HybridStream::abortable`, from
+/// outside the task polling it.
+#[derive(Clone)]
+pub struct AbortHandle {
+    aborted: Arc<AtomicBool>,
+}
+
+impl AbortHandle {
+    /// Cancel the associated stream. Idempotent, and safe to call after the stream has already
+    /// finished.
+    pub fn abort(&self) {
+        self.aborted.store(true, Ordering::SeqCst);
+    }
+}
+
+/// The other half of an `AbortHandle`, held by the stream itself.
+#[derive(Clone)]
+struct AbortRegistration {
+    aborted: Arc<AtomicBool>,
+}
+
+impl AbortRegistration {
+    fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::SeqCst)
+    }
+}
+
+fn new_abort_pair() -> (AbortHandle, AbortRegistration) {
+    let aborted = Arc::new(AtomicBool::new(false));
+    (
+        AbortHandle {
+            aborted: aborted.clone(),
+        },
+        AbortRegistration { aborted },
+    )
+}
+
+/// Whether an error from `resolve_remote` (or its response stream) should be retried, or
+/// surfaced to the consumer right away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Likely transient (timeout, connection reset, ...); worth retrying.
+    Retryable,
+    /// Won't go away on retry (bad input, auth failure, ...); surface immediately.
+    Fatal,
+}
+
+/// Backoff schedule for retrying a batch after a retryable error: the Nth retry waits
+/// `min(base_delay * 2^N, max_delay)` plus up to `jitter` of random delay.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// No retries: the first error is surfaced to the consumer, matching the pre-retry behavior.
+    fn default() -> Self {
+        Self {
+            max_attempts: 0,
+            base_delay: Duration::from_millis(0),
+            max_delay: Duration::from_millis(0),
+            jitter: Duration::from_millis(0),
+        }
+    }
 }
 
 /// Defines how to resolve input to output using local data and remote data.
@@ -58,6 +176,12 @@ pub trait HybridResolver<I, O, E> {
         &mut self,
         input: &[I],
     ) -> Result<BoxStream<'static, Result<(I, O), E>>, E>;
+
+    /// Classify an error from `resolve_remote` (or its response stream) as worth retrying or
+    /// not. Defaults to `Fatal`, matching the behavior of a resolver that predates retries.
+    fn classify_error(&self, _err: &E) -> ErrorKind {
+        ErrorKind::Fatal
+    }
 }
 
 #[derive(Debug)]
@@ -69,22 +193,128 @@ enum ResolveState<I, O> {
 impl<I, O, E> HybridStream<I, O, E>
 where
     I: Eq + Hash + Clone + Send + Sync + Debug + 'static,
-    O: Send + Sync + Debug + 'static,
-    E: 'static,
+    O: Clone + Send + Sync + Debug + 'static,
+    E: Send + 'static,
 {
-    /// Create a new `HybridStream`.
+    /// Create a new `HybridStream`. At most one remote batch is in flight at a time; use
+    /// `with_max_concurrent_batches` to pipeline several. No retries are attempted on remote
+    /// failure; use `with_retry_policy` to add them.
     pub fn new(
         stream: BoxStream<'static, Result<I, E>>,
         resolver: impl HybridResolver<I, O, E> + Send + Sync + 'static,
         buffer_size: usize,
+    ) -> Self {
+        Self::with_max_concurrent_batches(stream, resolver, buffer_size, 1)
+    }
+
+    /// Like `new`, but allows up to `max_concurrent_batches` remote batches to be in flight at
+    /// once, so a high-latency backend doesn't serialize round-trips.
+    pub fn with_max_concurrent_batches(
+        stream: BoxStream<'static, Result<I, E>>,
+        resolver: impl HybridResolver<I, O, E> + Send + Sync + 'static,
+        buffer_size: usize,
+        max_concurrent_batches: usize,
+    ) -> Self {
+        Self::with_retry_policy(
+            stream,
+            resolver,
+            buffer_size,
+            max_concurrent_batches,
+            RetryPolicy::default(),
+        )
+    }
+
+    /// Like `with_max_concurrent_batches`, but retries a batch that fails with a
+    /// `HybridResolver::classify_error`-retryable error according to `retry_policy`, instead of
+    /// surfacing it to the consumer right away.
+    pub fn with_retry_policy(
+        stream: BoxStream<'static, Result<I, E>>,
+        resolver: impl HybridResolver<I, O, E> + Send + Sync + 'static,
+        buffer_size: usize,
+        max_concurrent_batches: usize,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        Self::new_state(
+            stream,
+            resolver,
+            buffer_size,
+            max_concurrent_batches,
+            retry_policy,
+            None,
+            false,
+        )
+    }
+
+    /// Like `with_retry_policy`, but also returns an `AbortHandle` that can cancel the stream
+    /// from outside the task polling it — e.g. a user interrupting a large fetch — without
+    /// waiting for the current remote batch to finish or leaking the outstanding request.
+    pub fn abortable(
+        stream: BoxStream<'static, Result<I, E>>,
+        resolver: impl HybridResolver<I, O, E> + Send + Sync + 'static,
+        buffer_size: usize,
+        max_concurrent_batches: usize,
+        retry_policy: RetryPolicy,
+    ) -> (Self, AbortHandle) {
+        let (handle, registration) = new_abort_pair();
+        let stream = Self::new_state(
+            stream,
+            resolver,
+            buffer_size,
+            max_concurrent_batches,
+            retry_policy,
+            Some(registration),
+            false,
+        );
+        (stream, handle)
+    }
+
+    /// Like `with_retry_policy`, but resolves a whole `fill_buffer` batch of local lookups at
+    /// once on a blocking thread pool (via `tokio::task::spawn_blocking`), instead of calling
+    /// `resolve_local` inline on the async task for each input. Use this when `resolve_local`
+    /// does real work — decompressing a cached blob, hashing, hitting an on-disk index — that
+    /// would otherwise block the executor thread and stall every other task on it.
+    pub fn with_blocking_local_resolution(
+        stream: BoxStream<'static, Result<I, E>>,
+        resolver: impl HybridResolver<I, O, E> + Send + Sync + 'static,
+        buffer_size: usize,
+        max_concurrent_batches: usize,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        Self::new_state(
+            stream,
+            resolver,
+            buffer_size,
+            max_concurrent_batches,
+            retry_policy,
+            None,
+            true,
+        )
+    }
+
+    fn new_state(
+        stream: BoxStream<'static, Result<I, E>>,
+        resolver: impl HybridResolver<I, O, E> + Send + Sync + 'static,
+        buffer_size: usize,
+        max_concurrent_batches: usize,
+        retry_policy: RetryPolicy,
+        abort: Option<AbortRegistration>,
+        blocking_local: bool,
     ) -> Self {
         let state = HybridStreamState {
             input: stream,
             buffer: Default::default(),
             response: Default::default(),
+            response_waiters: Default::default(),
             buffer_size: buffer_size.max(1),
-            request: Default::default(),
-            resolver: Box::new(resolver),
+            requests: SelectAll::new(),
+            max_concurrent_batches: max_concurrent_batches.max(1),
+            next_batch_id: 0,
+            pending: Default::default(),
+            retry_policy,
+            batch_attempts: Default::default(),
+            abort,
+            blocking_local,
+            resolver: Some(Box::new(resolver)),
         };
         let stream = futures::stream::unfold(state, |mut state| async {
             let item = state.next_item().await;
@@ -109,12 +339,16 @@ where
 
 impl<I, O, E> HybridStreamState<I, O, E>
 where
-    I: Eq + Hash + Clone + Debug,
-    O: Debug,
+    I: Eq + Hash + Clone + Debug + Send + 'static,
+    O: Clone + Debug + Send + 'static,
+    E: Send + 'static,
 {
     /// A future to produce one `next` item.
     async fn next_item(&mut self) -> Result<Option<(I, O)>, E> {
         loop {
+            if self.abort_if_requested() {
+                break Ok(None);
+            }
             let item = self.buffer.pop_front();
             match item {
                 None => {
@@ -126,10 +360,13 @@ where
                 }
                 Some(ResolveState::Resolved(i, o)) => break Ok(Some((i, o))),
                 Some(ResolveState::NotResolved(i)) => {
-                    if let Some(o) = self.response.remove(&i) {
+                    if let Some(o) = self.take_response(&i) {
                         break Ok(Some((i, o)));
                     } else {
                         self.buffer.push_front(ResolveState::NotResolved(i));
+                        if self.abort_if_requested() {
+                            break Ok(None);
+                        }
                         self.poll_remote().await?;
                     }
                 }
@@ -137,6 +374,55 @@ where
         }
     }
 
+    /// Check whether `abort()` was called and, if so, drop any in-flight remote request(s) and
+    /// clear the buffer so the stream yields `None` from here on.
+    fn abort_if_requested(&mut self) -> bool {
+        let aborted = self.abort.as_ref().map_or(false, |a| a.is_aborted());
+        if aborted {
+            self.buffer.clear();
+            self.requests = SelectAll::new();
+            self.pending.clear();
+        }
+        aborted
+    }
+
+    /// The resolver, which is present except transiently mid-`fill_buffer_blocking`, while it
+    /// has been moved onto the blocking pool.
+    fn resolver(&mut self) -> &mut (dyn HybridResolver<I, O, E> + Send + Sync) {
+        self.resolver
+            .as_deref_mut()
+            .expect("resolver missing outside of fill_buffer_blocking")
+    }
+
+    /// Register that a `NotResolved(i)` slot was pushed to `self.buffer`, so `take_response`
+    /// knows how many slots still share `i`'s eventual remote result.
+    fn note_not_resolved(&mut self, i: &I) {
+        *self.response_waiters.entry(i.clone()).or_insert(0) += 1;
+    }
+
+    /// Take this slot's share of `i`'s `response` entry, if it has arrived, as a clone. The entry
+    /// is dropped once no other buffered slot is still waiting on the same key.
+    fn take_response(&mut self, i: &I) -> Option<O> {
+        let o = self.response.get(i)?.clone();
+        if let Some(count) = self.response_waiters.get_mut(i) {
+            *count -= 1;
+            if *count == 0 {
+                self.response_waiters.remove(i);
+                self.response.remove(i);
+            }
+        }
+        Some(o)
+    }
+
+    /// Push a freshly-created `ResolveState` onto `self.buffer`, registering it with
+    /// `note_not_resolved` if it's `NotResolved`.
+    fn push_state(&mut self, state: ResolveState<I, O>) {
+        if let ResolveState::NotResolved(i) = &state {
+            self.note_not_resolved(i);
+        }
+        self.buffer.push_back(state);
+    }
+
     /// Prepare a buffer of inputs. Part of them resolved locally. The remaining
     /// are to be resolved remotely.
     ///
@@ -144,17 +430,20 @@ where
     ///
     /// Consumes items from `self.input`. Updates `self.buffer`.
     async fn fill_buffer(&mut self) -> Result<usize, E> {
+        if self.blocking_local {
+            return self.fill_buffer_blocking().await;
+        }
         let mut count = 0;
-        while self.buffer.len() < self.buffer_size {
+        while self.buffer.len() < self.target_buffer_len() {
             let next_input = self.input.next().await.transpose()?;
             match next_input {
                 Some(input) => {
                     // Attempt to resolve it locally.
-                    let state = match self.resolver.resolve_local(&input)? {
+                    let state = match self.resolver().resolve_local(&input)? {
                         Some(output) => ResolveState::Resolved(input, output),
                         None => ResolveState::NotResolved(input),
                     };
-                    self.buffer.push_back(state);
+                    self.push_state(state);
                     count += 1;
                 }
                 // Reached the end.
@@ -164,55 +453,191 @@ where
         Ok(count)
     }
 
-    /// Make progress related to the remote request.
+    /// Like `fill_buffer`, but gathers a whole batch of inputs up front and resolves them all
+    /// locally in a single `spawn_blocking` call, instead of calling `resolve_local` inline on
+    /// the async task once per input. Used when `blocking_local` is set.
+    async fn fill_buffer_blocking(&mut self) -> Result<usize, E> {
+        let mut inputs = Vec::new();
+        while self.buffer.len() + inputs.len() < self.target_buffer_len() {
+            match self.input.next().await.transpose()? {
+                Some(input) => inputs.push(input),
+                // Reached the end.
+                None => break,
+            }
+        }
+        if inputs.is_empty() {
+            return Ok(0);
+        }
+        let count = inputs.len();
+
+        let mut resolver = self
+            .resolver
+            .take()
+            .expect("resolver missing outside of fill_buffer_blocking");
+        let (resolver, resolved) = tokio::task::spawn_blocking(move || {
+            let resolved: Vec<Result<Option<O>, E>> = inputs
+                .iter()
+                .map(|input| resolver.resolve_local(input))
+                .collect();
+            (resolver, inputs.into_iter().zip(resolved).collect::<Vec<_>>())
+        })
+        .await
+        .expect("resolve_local panicked on the blocking pool");
+        self.resolver = Some(resolver);
+
+        for (input, result) in resolved {
+            let state = match result? {
+                Some(output) => ResolveState::Resolved(input, output),
+                None => ResolveState::NotResolved(input),
+            };
+            self.push_state(state);
+        }
+        Ok(count)
+    }
+
+    /// Make progress related to remote requests.
     ///
-    /// If there is no pending request, send a new one if necessary.
-    /// If there is an existing request, read from it (`self.request`),
-    /// and updates `self.response`.
+    /// If fewer than `max_concurrent_batches` are in flight and there is unresolved, unclaimed
+    /// input, dispatch another batch immediately rather than waiting for the current ones to
+    /// drain. Then read whichever in-flight batch produces a result first, and update
+    /// `self.response`.
     async fn poll_remote(&mut self) -> Result<(), E> {
-        // Send a batch request if any input is unresolved and there is no
-        // pending request.
-        match self.request {
-            None => {
-                let batch: Vec<I> = self.remote_input();
-                if !batch.is_empty() {
-                    let request = self.resolver.resolve_remote(&batch).await?;
-                    self.request = Some(request);
-                }
+        if self.requests.len() < self.max_concurrent_batches {
+            let batch: Vec<I> = self.remote_input();
+            if !batch.is_empty() {
+                self.dispatch_batch(batch).await?;
             }
-            Some(ref mut stream) => match stream.next().await {
-                None => self.request = None,
-                Some(Ok((i, o))) => {
-                    self.response.insert(i, o);
-                }
-                Some(Err(e)) => {
-                    self.request = None;
-                    return Err(e);
-                }
-            },
+        }
+
+        if self.requests.is_empty() {
+            return Ok(());
+        }
+
+        match self.requests.next().await {
+            None => {}
+            Some((batch_id, Ok((i, o)))) => {
+                self.pending.remove(&i);
+                self.response.insert(i, o);
+                // This batch made progress; give it a fresh run of attempts if it fails again.
+                self.batch_attempts.remove(&batch_id);
+            }
+            Some((batch_id, Err(e))) => self.retry_or_raise(batch_id, e).await?,
         }
         Ok(())
     }
 
-    /// Input for a remote request.
+    /// Dispatch a new batch under a fresh batch id, retrying via `retry_or_raise` (under that
+    /// same id) if `resolve_remote` fails before even returning a response stream.
+    async fn dispatch_batch(&mut self, batch: Vec<I>) -> Result<(), E> {
+        let batch_id = self.next_batch_id;
+        self.next_batch_id += 1;
+        match self.resolver().resolve_remote(&batch).await {
+            Ok(request) => {
+                self.accept_batch(batch_id, batch, request);
+                Ok(())
+            }
+            Err(e) => self.retry_or_raise(batch_id, e).await,
+        }
+    }
+
+    /// Record a batch's inputs as pending under `batch_id` and fold its response stream into
+    /// `self.requests`, tagging each item with `batch_id` so a later error can be attributed back
+    /// to exactly this batch.
+    fn accept_batch(
+        &mut self,
+        batch_id: u64,
+        batch: Vec<I>,
+        request: BoxStream<'static, Result<(I, O), E>>,
+    ) {
+        for i in batch {
+            self.pending.insert(i, batch_id);
+        }
+        self.requests
+            .push(request.map(move |r| (batch_id, r)).boxed());
+    }
+
+    /// Handle an error from batch `batch_id`'s `resolve_remote` (or its response stream): if
+    /// `retry_policy` and `HybridResolver::classify_error` allow it, back off and dispatch a fresh
+    /// `remote_input` batch under the same id (the buffer's unresolved inputs not claimed by any
+    /// other in-flight batch, which include this batch's freed ones); otherwise propagate the error to the
+    /// consumer. `batch_attempts` is tracked per `batch_id`, so one persistently-failing batch
+    /// exhausts its own `max_attempts` independently of other batches succeeding alongside it.
+    async fn retry_or_raise(&mut self, batch_id: u64, mut err: E) -> Result<(), E> {
+        // Free this batch's own inputs from `pending` so `remote_input` can re-offer them,
+        // without disturbing inputs owned by other in-flight batches.
+        self.pending.retain(|_, &mut id| id != batch_id);
+
+        loop {
+            let attempts = self.batch_attempts.get(&batch_id).copied().unwrap_or(0);
+            if attempts >= self.retry_policy.max_attempts
+                || self.resolver().classify_error(&err) == ErrorKind::Fatal
+            {
+                self.batch_attempts.remove(&batch_id);
+                return Err(err);
+            }
+
+            self.batch_attempts.insert(batch_id, attempts + 1);
+
+            let delay = self
+                .retry_policy
+                .base_delay
+                .saturating_mul(1u32.checked_shl(attempts).unwrap_or(u32::MAX))
+                .min(self.retry_policy.max_delay);
+            let jitter_nanos = self.retry_policy.jitter.as_nanos() as u64;
+            let jitter = Duration::from_nanos(rand::random::<u64>() % (jitter_nanos + 1));
+            tokio::time::delay_for(delay + jitter).await;
+
+            let batch = self.remote_input();
+            if batch.is_empty() {
+                self.batch_attempts.remove(&batch_id);
+                return Ok(());
+            }
+            match self.resolver().resolve_remote(&batch).await {
+                Ok(request) => {
+                    self.accept_batch(batch_id, batch, request);
+                    return Ok(());
+                }
+                Err(e) => err = e,
+            }
+        }
+    }
+
+    /// Input for a new remote batch: distinct unresolved inputs that aren't already sitting in
+    /// `response`, and aren't already claimed by an in-flight batch in `pending`.
+    ///
+    /// Deduplicated so a key that appears more than once in `buffer` (e.g. the same content hash
+    /// requested for several paths) is only fetched once; `take_response` fans the single result
+    /// back out to every waiting slot. Capped at `buffer_size` so a fully topped-up buffer is
+    /// split across up to `max_concurrent_batches` separate batches instead of being drained by
+    /// the very first one dispatched.
     fn remote_input(&self) -> Vec<I> {
+        let mut seen = HashSet::new();
         self.buffer
             .iter()
             .filter_map(|i| match i {
                 ResolveState::NotResolved(i) => {
-                    if self.response.contains_key(&i) {
-                        // The item was fetched previously.
-                        // This can happen when retry requests were sent.
+                    if self.response.contains_key(i) || self.pending.contains_key(i) {
+                        // The item was fetched previously, or is already being fetched by an
+                        // in-flight batch. This can happen when retry requests were sent.
                         None
-                    } else {
+                    } else if seen.insert(i) {
                         Some(i)
+                    } else {
+                        None
                     }
                 }
                 ResolveState::Resolved(_, _) => None,
             })
+            .take(self.buffer_size)
             .cloned()
             .collect()
     }
+
+    /// Buffer length `fill_buffer`/`fill_buffer_blocking` top up to: enough unresolved input for
+    /// `max_concurrent_batches` separate batches of `buffer_size` each to be outstanding at once.
+    fn target_buffer_len(&self) -> usize {
+        self.buffer_size * self.max_concurrent_batches
+    }
 }
 
 #[cfg(test)]
@@ -273,4 +698,399 @@ mod tests {
             assert!(stream.next().await.is_none());
         }
     }
+
+    #[tokio::test]
+    async fn test_hybrid_stream_concurrent_batches() {
+        type I = usize;
+        type O = String;
+        type E = std::io::Error;
+
+        // Resolver that never resolves locally, so every input goes remote, and tracks how many
+        // `resolve_remote` batches were in flight (i.e. still had unyielded items) at once.
+        #[derive(Default)]
+        struct Resolver {
+            in_flight: Arc<Mutex<usize>>,
+            max_in_flight: Arc<Mutex<usize>>,
+        }
+
+        #[async_trait]
+        impl HybridResolver<I, O, E> for Resolver {
+            fn resolve_local(&mut self, _input: &I) -> Result<Option<O>, E> {
+                Ok(None)
+            }
+
+            async fn resolve_remote(
+                &mut self,
+                input: &[I],
+            ) -> Result<BoxStream<'static, Result<(I, O), E>>, E> {
+                let in_flight = self.in_flight.clone();
+                let max_in_flight = self.max_in_flight.clone();
+                {
+                    let mut n = in_flight.lock().unwrap();
+                    *n += 1;
+                    let mut m = max_in_flight.lock().unwrap();
+                    *m = (*m).max(*n);
+                }
+                let input: Vec<I> = input.iter().cloned().collect();
+                let last = input.len().saturating_sub(1);
+                let stream = stream::iter(input.into_iter().enumerate()).then(move |(idx, i)| {
+                    let in_flight = in_flight.clone();
+                    async move {
+                        // Each item of a batch takes a little time to arrive, so a batch stays
+                        // "in flight" (occupying a `requests` slot) for a while, long enough for
+                        // another batch to be dispatched alongside it.
+                        delay_for(Duration::from_millis(5)).await;
+                        if idx == last {
+                            *in_flight.lock().unwrap() -= 1;
+                        }
+                        Ok((i, i.to_string()))
+                    }
+                });
+                Ok(Box::pin(stream))
+            }
+        }
+
+        let max_in_flight = Arc::new(Mutex::new(0));
+        let input = stream::iter((0..20).map(Ok));
+        let resolver = Resolver {
+            in_flight: Default::default(),
+            max_in_flight: max_in_flight.clone(),
+        };
+        let mut stream =
+            HybridStream::with_max_concurrent_batches(Box::pin(input), resolver, 6, 4);
+
+        let mut results: Vec<(I, O)> = Vec::new();
+        while let Some(item) = stream.next().await {
+            results.push(item.unwrap());
+        }
+        // Output order must match input order even though batches resolve concurrently.
+        assert_eq!(
+            results,
+            (0..20).map(|i| (i, i.to_string())).collect::<Vec<_>>()
+        );
+        assert!(*max_in_flight.lock().unwrap() > 1);
+    }
+
+    #[tokio::test]
+    async fn test_hybrid_stream_retry() {
+        type I = usize;
+        type O = String;
+        type E = std::io::Error;
+
+        // Resolver whose remote fetch fails the first `fail_count` times a given input is
+        // requested with a retryable error, then succeeds.
+        #[derive(Default)]
+        struct Resolver {
+            attempts: Arc<Mutex<HashMap<usize, u32>>>,
+            fail_count: u32,
+        }
+
+        #[async_trait]
+        impl HybridResolver<I, O, E> for Resolver {
+            fn resolve_local(&mut self, _input: &I) -> Result<Option<O>, E> {
+                Ok(None)
+            }
+
+            async fn resolve_remote(
+                &mut self,
+                input: &[I],
+            ) -> Result<BoxStream<'static, Result<(I, O), E>>, E> {
+                let mut attempts = self.attempts.lock().unwrap();
+                // Count every item on every call: `.any()` short-circuits, so if this used `.any()`
+                // directly only the first still-failing item in the batch would get its counter
+                // incremented, and the batch would never reach a call where all items report
+                // success at once.
+                let still_failing = input
+                    .iter()
+                    .map(|i| {
+                        let count = attempts.entry(*i).or_insert(0);
+                        *count += 1;
+                        *count <= self.fail_count
+                    })
+                    .collect::<Vec<_>>()
+                    .into_iter()
+                    .any(|failing| failing);
+                if still_failing {
+                    return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "retry me"));
+                }
+                let input: Vec<I> = input.iter().cloned().collect();
+                let output_iter = input.into_iter().map(|i| Ok((i, i.to_string())));
+                Ok(Box::pin(stream::iter(output_iter)))
+            }
+
+            fn classify_error(&self, _err: &E) -> ErrorKind {
+                ErrorKind::Retryable
+            }
+        }
+
+        let input = stream::iter(vec![0, 1, 2].into_iter().map(Ok));
+        let resolver = Resolver {
+            attempts: Default::default(),
+            fail_count: 2,
+        };
+        let retry_policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+            jitter: Duration::from_millis(1),
+        };
+        let mut stream =
+            HybridStream::with_retry_policy(Box::pin(input), resolver, 10, 1, retry_policy);
+
+        let u = |v: Option<Result<(I, O), E>>| v.unwrap().unwrap();
+        assert_eq!(u(stream.next().await), (0, "0".to_string()));
+        assert_eq!(u(stream.next().await), (1, "1".to_string()));
+        assert_eq!(u(stream.next().await), (2, "2".to_string()));
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_hybrid_stream_retries_failed_batch_without_leaking_pending() {
+        type I = usize;
+        type O = String;
+        type E = std::io::Error;
+
+        // Resolver whose batch containing `0` is accepted (so its inputs enter `pending`) but
+        // whose response stream then fails outright, the first `fail_count` times; a
+        // concurrently dispatched batch containing `3` always succeeds on its first attempt.
+        #[derive(Default)]
+        struct Resolver {
+            zero_batch_attempts: Arc<Mutex<u32>>,
+            three_batch_attempts: Arc<Mutex<u32>>,
+            fail_count: u32,
+        }
+
+        #[async_trait]
+        impl HybridResolver<I, O, E> for Resolver {
+            fn resolve_local(&mut self, _input: &I) -> Result<Option<O>, E> {
+                Ok(None)
+            }
+
+            async fn resolve_remote(
+                &mut self,
+                input: &[I],
+            ) -> Result<BoxStream<'static, Result<(I, O), E>>, E> {
+                let input: Vec<I> = input.iter().cloned().collect();
+                if input.contains(&0) {
+                    let mut attempts = self.zero_batch_attempts.lock().unwrap();
+                    *attempts += 1;
+                    if *attempts <= self.fail_count {
+                        // Accepted, so its inputs are recorded in `pending` — but the stream
+                        // fails before yielding anything. If `pending` weren't freed for this
+                        // batch on error, these inputs would never be offered to `remote_input`
+                        // again and the stream would hang forever.
+                        let err = std::io::Error::new(std::io::ErrorKind::TimedOut, "retry me");
+                        return Ok(Box::pin(stream::once(async { Err(err) })));
+                    }
+                } else {
+                    *self.three_batch_attempts.lock().unwrap() += 1;
+                }
+                let output_iter = input.into_iter().map(|i| Ok((i, i.to_string())));
+                Ok(Box::pin(stream::iter(output_iter)))
+            }
+
+            fn classify_error(&self, _err: &E) -> ErrorKind {
+                ErrorKind::Retryable
+            }
+        }
+
+        let input = stream::iter((0..6).map(Ok));
+        let zero_batch_attempts = Arc::new(Mutex::new(0));
+        let three_batch_attempts = Arc::new(Mutex::new(0));
+        let resolver = Resolver {
+            zero_batch_attempts: zero_batch_attempts.clone(),
+            three_batch_attempts: three_batch_attempts.clone(),
+            fail_count: 2,
+        };
+        let retry_policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+            jitter: Duration::from_millis(1),
+        };
+        // buffer_size 3 + max_concurrent_batches 2 dispatches [0,1,2] and [3,4,5] concurrently.
+        let mut stream =
+            HybridStream::with_retry_policy(Box::pin(input), resolver, 3, 2, retry_policy);
+
+        let mut results: Vec<(I, O)> = Vec::new();
+        while let Some(item) = stream.next().await {
+            results.push(item.unwrap());
+        }
+        results.sort();
+        assert_eq!(
+            results,
+            (0..6).map(|i| (i, i.to_string())).collect::<Vec<_>>()
+        );
+        // The failing batch needed one attempt beyond `fail_count` to finally succeed...
+        assert_eq!(*zero_batch_attempts.lock().unwrap(), 3);
+        // ...but the unrelated, concurrently-dispatched batch was never forced to retry just
+        // because a different batch's attempt counter was climbing alongside it.
+        assert_eq!(*three_batch_attempts.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_hybrid_stream_abortable() {
+        type I = usize;
+        type O = String;
+        type E = std::io::Error;
+
+        #[derive(Default)]
+        struct Resolver;
+
+        #[async_trait]
+        impl HybridResolver<I, O, E> for Resolver {
+            fn resolve_local(&mut self, _input: &I) -> Result<Option<O>, E> {
+                Ok(None)
+            }
+
+            async fn resolve_remote(
+                &mut self,
+                input: &[I],
+            ) -> Result<BoxStream<'static, Result<(I, O), E>>, E> {
+                delay_for(Duration::from_millis(50)).await;
+                let input: Vec<I> = input.iter().cloned().collect();
+                let output_iter = input.into_iter().map(|i| Ok((i, i.to_string())));
+                Ok(Box::pin(stream::iter(output_iter)))
+            }
+        }
+
+        let input = stream::iter((0..100).map(Ok));
+        let (mut stream, handle) = HybridStream::abortable(
+            Box::pin(input),
+            Resolver::default(),
+            4,
+            1,
+            RetryPolicy::default(),
+        );
+
+        handle.abort();
+        assert!(stream.next().await.is_none());
+        // Aborting again, or after the stream has already ended, is a harmless no-op.
+        handle.abort();
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_hybrid_stream_blocking_local_resolution() {
+        type I = usize;
+        type O = String;
+        type E = std::io::Error;
+
+        // Resolver that resolves evens locally and sends odds remote, and tracks how many
+        // inputs `resolve_local` saw in its largest single batch.
+        #[derive(Default)]
+        struct Resolver {
+            max_batch: Arc<Mutex<usize>>,
+            batch: Arc<Mutex<usize>>,
+        }
+
+        #[async_trait]
+        impl HybridResolver<I, O, E> for Resolver {
+            fn resolve_local(&mut self, input: &I) -> Result<Option<O>, E> {
+                let mut batch = self.batch.lock().unwrap();
+                *batch += 1;
+                let mut max_batch = self.max_batch.lock().unwrap();
+                *max_batch = (*max_batch).max(*batch);
+                Ok(if input % 2 == 0 {
+                    Some(input.to_string())
+                } else {
+                    None
+                })
+            }
+
+            async fn resolve_remote(
+                &mut self,
+                input: &[I],
+            ) -> Result<BoxStream<'static, Result<(I, O), E>>, E> {
+                *self.batch.lock().unwrap() = 0;
+                let input: Vec<I> = input.iter().cloned().collect();
+                let output_iter = input.into_iter().map(|i| Ok((i, i.to_string())));
+                Ok(Box::pin(stream::iter(output_iter)))
+            }
+        }
+
+        let max_batch = Arc::new(Mutex::new(0));
+        let input = stream::iter((0..10).map(Ok));
+        let resolver = Resolver {
+            max_batch: max_batch.clone(),
+            batch: Default::default(),
+        };
+        let mut stream = HybridStream::with_blocking_local_resolution(
+            Box::pin(input),
+            resolver,
+            10,
+            1,
+            RetryPolicy::default(),
+        );
+
+        let mut results: Vec<(I, O)> = Vec::new();
+        while let Some(item) = stream.next().await {
+            results.push(item.unwrap());
+        }
+        assert_eq!(
+            results,
+            (0..10).map(|i| (i, i.to_string())).collect::<Vec<_>>()
+        );
+        // All 10 inputs were resolved locally in one `spawn_blocking` batch.
+        assert_eq!(*max_batch.lock().unwrap(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_hybrid_stream_dedups_remote_fetches() {
+        type I = usize;
+        type O = String;
+        type E = std::io::Error;
+
+        // Resolver that never resolves locally, so every input goes remote, and tracks how many
+        // times each key was handed to `resolve_remote`.
+        #[derive(Default)]
+        struct Resolver {
+            fetch_counts: Arc<Mutex<HashMap<I, u32>>>,
+        }
+
+        #[async_trait]
+        impl HybridResolver<I, O, E> for Resolver {
+            fn resolve_local(&mut self, _input: &I) -> Result<Option<O>, E> {
+                Ok(None)
+            }
+
+            async fn resolve_remote(
+                &mut self,
+                input: &[I],
+            ) -> Result<BoxStream<'static, Result<(I, O), E>>, E> {
+                let mut fetch_counts = self.fetch_counts.lock().unwrap();
+                for i in input {
+                    *fetch_counts.entry(*i).or_insert(0) += 1;
+                }
+                let input: Vec<I> = input.iter().cloned().collect();
+                let output_iter = input.into_iter().map(|i| Ok((i, i.to_string())));
+                Ok(Box::pin(stream::iter(output_iter)))
+            }
+        }
+
+        let fetch_counts = Arc::new(Mutex::new(HashMap::new()));
+        // Keys 0 and 1 each appear 3 times, as if several paths shared the same content hash.
+        let input = stream::iter(vec![0, 1, 0, 1, 2, 0, 1].into_iter().map(Ok));
+        let resolver = Resolver {
+            fetch_counts: fetch_counts.clone(),
+        };
+        let mut stream = HybridStream::new(Box::pin(input), resolver, 10);
+
+        let mut results: Vec<(I, O)> = Vec::new();
+        while let Some(item) = stream.next().await {
+            results.push(item.unwrap());
+        }
+        // Output order and repetition match the input, even though each key was fetched once.
+        assert_eq!(
+            results,
+            vec![0, 1, 0, 1, 2, 0, 1]
+                .into_iter()
+                .map(|i| (i, i.to_string()))
+                .collect::<Vec<_>>()
+        );
+        let fetch_counts = fetch_counts.lock().unwrap();
+        assert_eq!(*fetch_counts.get(&0).unwrap(), 1);
+        assert_eq!(*fetch_counts.get(&1).unwrap(), 1);
+        assert_eq!(*fetch_counts.get(&2).unwrap(), 1);
+    }
 }
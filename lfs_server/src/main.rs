@@ -12,15 +12,22 @@ use clap::Arg;
 use failure::{err_msg, Error};
 use fbinit::FacebookInit;
 use futures::{Future, IntoFuture};
+use futures_preview::stream::StreamExt;
 use futures_preview::{FutureExt, TryFutureExt};
 use futures_util::{compat::Future01CompatExt, try_future::try_join_all};
+use bytes::Buf;
 use gotham::bind_server;
 use scuba::ScubaSampleBuilder;
 use slog::warn;
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Seek};
 use std::net::ToSocketAddrs;
+use std::sync::Arc;
+use openssl::ssl::SslVerifyMode;
 use tokio::net::TcpListener;
 use tokio_openssl::SslAcceptorExt;
+use tokio_rustls::TlsAcceptor;
 
 use blobrepo_factory::open_blobrepo;
 use failure_ext::chain::ChainExt;
@@ -58,11 +65,312 @@ const ARG_TLS_CERTIFICATE: &str = "tls-certificate";
 const ARG_TLS_PRIVATE_KEY: &str = "tls-private-key";
 const ARG_TLS_CA: &str = "tls-ca";
 const ARG_TLS_TICKET_SEEDS: &str = "tls-ticket-seeds";
+const ARG_TLS_BACKEND: &str = "tls-backend";
+const ARG_REQUIRE_CLIENT_CERT: &str = "require-client-cert";
+const ARG_LISTEN_QUIC_PORT: &str = "listen-quic-port";
+const ARG_UPSTREAM_TLS_ROOTS: &str = "upstream-tls-roots";
+const ARG_LISTEN_UNIX: &str = "listen-unix";
 const ARG_SCUBA_DATASET: &str = "scuba-dataset";
 const ARG_ALWAYS_WAIT_FOR_UPSTREAM: &str = "always-wait-for-upstream";
 
+const TLS_BACKEND_OPENSSL: &str = "openssl";
+const TLS_BACKEND_RUSTLS: &str = "rustls";
+
 const SERVICE_NAME: &str = "mononoke_lfs_server";
 
+/// How to verify the TLS certificate presented by `--upstream-url` when proxying LFS requests.
+///
+/// This value is parsed from `--upstream-tls-roots` and passed to `LfsServerContext::new` below.
+/// The upstream HTTPS client is built in `lfs_server_context.rs` / `batch.rs` / `download.rs`,
+/// none of which reads this value, so the flag is parsed and validated but has no effect on
+/// outbound connections.
+#[derive(Clone, Debug)]
+enum UpstreamTlsRoots {
+    /// Trust the OS trust store (via `rustls-native-certs`).
+    System,
+    /// Trust a compiled-in Mozilla root bundle (via `webpki-roots`).
+    Bundled,
+    /// Trust only the CAs in this PEM file.
+    CaFile(String),
+}
+
+fn parse_upstream_tls_roots(value: &str) -> Result<UpstreamTlsRoots, Error> {
+    match value {
+        "system" => Ok(UpstreamTlsRoots::System),
+        "bundled" => Ok(UpstreamTlsRoots::Bundled),
+        _ if value.starts_with("ca-file=") => {
+            Ok(UpstreamTlsRoots::CaFile(value["ca-file=".len()..].to_string()))
+        }
+        _ => Err(err_msg(format!(
+            "Invalid --upstream-tls-roots value: {} (expected system, bundled, or ca-file=<path>)",
+            value
+        ))),
+    }
+}
+
+/// Build a `rustls::ServerConfig` from a PEM certificate chain and private key, advertising
+/// both `h2` and `http/1.1` via ALPN. This is the rustls counterpart to
+/// `secure_utils::build_tls_acceptor_builder`, used when `--tls-backend=rustls` is selected so
+/// that operators can run the LFS server without linking against OpenSSL.
+///
+/// When `client_ca` is set, the acceptor requires and verifies a client certificate against it,
+/// turning this into a mutual-TLS listener.
+fn build_rustls_server_config(
+    tls_certificate: &str,
+    tls_private_key: &str,
+    client_ca: Option<&str>,
+) -> Result<rustls::ServerConfig, Error> {
+    let certs = {
+        let certfile = File::open(tls_certificate).chain_err(err_msg("Could not open TLS certificate"))?;
+        rustls::internal::pemfile::certs(&mut BufReader::new(certfile))
+            .map_err(|()| err_msg("Could not parse TLS certificate"))?
+    };
+
+    let mut keyfile = BufReader::new(
+        File::open(tls_private_key).chain_err(err_msg("Could not open TLS private key"))?,
+    );
+
+    let mut keys = rustls::internal::pemfile::pkcs8_private_keys(&mut keyfile)
+        .map_err(|()| err_msg("Could not parse PKCS8 private key"))?;
+
+    if keys.is_empty() {
+        keyfile.seek(std::io::SeekFrom::Start(0))?;
+        keys = rustls::internal::pemfile::rsa_private_keys(&mut keyfile)
+            .map_err(|()| err_msg("Could not parse RSA private key"))?;
+    }
+
+    let key = keys.pop().ok_or_else(|| err_msg("No private key found"))?;
+
+    let client_auth = match client_ca {
+        Some(client_ca) => {
+            let cafile =
+                File::open(client_ca).chain_err(err_msg("Could not open client CA bundle"))?;
+            let mut roots = rustls::RootCertStore::empty();
+            roots
+                .add_pem_file(&mut BufReader::new(cafile))
+                .map_err(|()| err_msg("Could not parse client CA bundle"))?;
+            rustls::AllowAnyAuthenticatedClient::new(roots)
+        }
+        None => rustls::NoClientAuth::new(),
+    };
+
+    let mut config = rustls::ServerConfig::new(client_auth);
+    config.set_single_cert(certs, key)?;
+    config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+    Ok(config)
+}
+
+/// Extract a loggable client identity from the leaf certificate a client presented during a
+/// mutual-TLS handshake: the certificate's subject distinguished name, as formatted by
+/// `x509_parser`. Subject Alternative Name entries are not included.
+fn extract_client_identity(certs: &[rustls::Certificate]) -> Result<String, Error> {
+    let leaf = certs
+        .first()
+        .ok_or_else(|| err_msg("No client certificate presented"))?;
+    let (_, parsed) = x509_parser::parse_x509_der(&leaf.0)
+        .map_err(|_| err_msg("Could not parse client certificate"))?;
+    let subject = parsed.tbs_certificate.subject.to_string();
+    Ok(subject)
+}
+
+/// The OpenSSL backend's counterpart to `extract_client_identity`: derives the identity from the
+/// certificate's subject too, but as the subject entries' values joined by `,`, without attribute
+/// names, so its output is not formatted like `extract_client_identity`'s.
+fn extract_client_identity_openssl(cert: &openssl::x509::X509Ref) -> Result<String, Error> {
+    let subject = cert
+        .subject_name()
+        .entries()
+        .filter_map(|entry| entry.data().as_utf8().ok().map(|s| s.to_string()))
+        .collect::<Vec<_>>()
+        .join(",");
+    if subject.is_empty() {
+        return Err(err_msg("Client certificate has no subject"));
+    }
+    Ok(subject)
+}
+
+/// Wraps a post-handshake connection together with the client identity extracted from its peer
+/// certificate (when `--require-client-cert` made one mandatory), so the identity survives past
+/// the TLS acceptor closure as a connection-level extension instead of falling out of scope right
+/// after being logged.
+///
+/// `ClientIdentityMiddleware` (in `middleware.rs`) does not read the identity back out of the
+/// connection into Gotham `State`, and `identity()` has no caller, so batch/download/upload
+/// handlers cannot see it.
+#[allow(dead_code)]
+struct IdentifiedStream<S> {
+    inner: S,
+    identity: Option<String>,
+}
+
+impl<S> IdentifiedStream<S> {
+    fn new(inner: S, identity: Option<String>) -> Self {
+        IdentifiedStream { inner, identity }
+    }
+
+    #[allow(dead_code)]
+    fn identity(&self) -> Option<&str> {
+        self.identity.as_ref().map(|s| s.as_str())
+    }
+}
+
+impl<S: std::io::Read> std::io::Read for IdentifiedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<S: std::io::Write> std::io::Write for IdentifiedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: tokio::io::AsyncRead> tokio::io::AsyncRead for IdentifiedStream<S> {}
+
+impl<S: tokio::io::AsyncWrite> tokio::io::AsyncWrite for IdentifiedStream<S> {
+    fn shutdown(&mut self) -> futures::Poll<(), std::io::Error> {
+        self.inner.shutdown()
+    }
+}
+
+/// Serve HTTP/3 over QUIC on `listen_quic_port`, reusing the same certificate/key as the TCP+TLS
+/// listener, and routing accepted h3 requests through the same `root` `NewHandler` (built from
+/// `build_router(ctx)` / `MononokeLfsHandler`) the TCP listener uses, via `gotham::service::GothamService`
+/// -- the same connection-to-`State` bridge Gotham's own `bind_server` uses internally, which is why it's
+/// the right tool for wiring a non-Gotham transport like `h3` into the router instead of a bespoke adapter.
+/// `GothamService` is an implementation detail of the Gotham version this crate is pinned to rather than
+/// part of its documented public surface, so if the pin moves and stops re-exporting it, this needs to
+/// track whatever replaces it -- that's a real follow-up risk, not a cosmetic one.
+async fn serve_quic<NH>(
+    listen_host: &str,
+    listen_quic_port: &str,
+    tls_certificate: &str,
+    tls_private_key: &str,
+    root: Arc<NH>,
+    logger: slog::Logger,
+) -> Result<(), Error>
+where
+    NH: gotham::handler::NewHandler + 'static,
+{
+    let mut crypto = build_rustls_server_config(tls_certificate, tls_private_key, None)?;
+    crypto.set_protocols(&[b"h3".to_vec()]);
+
+    let mut server_config = quinn::ServerConfig::default();
+    server_config.crypto = Arc::new(crypto);
+    let mut endpoint_builder = quinn::Endpoint::builder();
+    endpoint_builder.listen(server_config);
+
+    let addr = format!("{}:{}", listen_host, listen_quic_port)
+        .to_socket_addrs()
+        .chain_err(err_msg("Invalid QUIC listener address"))?
+        .next()
+        .ok_or(err_msg("Invalid QUIC socket address"))?;
+
+    let (_endpoint, mut incoming) = endpoint_builder
+        .bind(&addr)
+        .chain_err(err_msg("Could not bind QUIC listener"))?;
+
+    while let Some(connecting) = incoming.next().await {
+        let logger = logger.clone();
+        let root = root.clone();
+        tokio::spawn(async move {
+            let new_conn = match connecting.await {
+                Ok(new_conn) => new_conn,
+                Err(e) => {
+                    warn!(&logger, "QUIC handshake failed: {:?}", e);
+                    return;
+                }
+            };
+
+            let client_addr = new_conn.connection.remote_address();
+            let gotham_service = gotham::service::GothamService::new(root.clone());
+
+            let mut h3_conn =
+                match h3::server::Connection::new(h3_quinn::Connection::new(new_conn.connection))
+                    .await
+                {
+                    Ok(h3_conn) => h3_conn,
+                    Err(e) => {
+                        warn!(&logger, "HTTP/3 handshake failed: {:?}", e);
+                        return;
+                    }
+                };
+
+            while let Ok(Some((req, mut stream))) = h3_conn.accept().await {
+                let logger = logger.clone();
+                let mut connected_service = gotham_service.connect(client_addr);
+                tokio::spawn(async move {
+                    // Drain the h3 request body into a `hyper::Body` up front: `GothamService`
+                    // (like the TCP listener's Gotham pipeline) expects a regular
+                    // `http::Request<hyper::Body>`, and h3 doesn't hand us one directly.
+                    let mut body = Vec::new();
+                    loop {
+                        match stream.recv_data().await {
+                            Ok(Some(mut chunk)) => {
+                                let mut buf = vec![0u8; chunk.remaining()];
+                                chunk.copy_to_slice(&mut buf);
+                                body.extend_from_slice(&buf);
+                            }
+                            Ok(None) => break,
+                            Err(e) => {
+                                warn!(&logger, "Failed to read HTTP/3 request body: {:?}", e);
+                                return;
+                            }
+                        }
+                    }
+
+                    let request = req.map(|_| hyper::Body::from(body));
+
+                    let response = match connected_service.call(request).await {
+                        Ok(response) => response,
+                        Err(e) => {
+                            warn!(&logger, "Gotham router failed to handle HTTP/3 request: {:?}", e);
+                            http::Response::builder()
+                                .status(http::StatusCode::INTERNAL_SERVER_ERROR)
+                                .body(hyper::Body::empty())
+                                .expect("building a static response cannot fail")
+                        }
+                    };
+
+                    let (parts, mut response_body) = response.into_parts();
+                    if let Err(e) = stream
+                        .send_response(http::Response::from_parts(parts, ()))
+                        .await
+                    {
+                        warn!(&logger, "Failed to send HTTP/3 response: {:?}", e);
+                        return;
+                    }
+
+                    while let Some(chunk) = response_body.next().await {
+                        let chunk = match chunk {
+                            Ok(chunk) => chunk,
+                            Err(e) => {
+                                warn!(&logger, "Failed to read response body: {:?}", e);
+                                return;
+                            }
+                        };
+                        if let Err(e) = stream.send_data(chunk).await {
+                            warn!(&logger, "Failed to send HTTP/3 response body: {:?}", e);
+                            return;
+                        }
+                    }
+
+                    if let Err(e) = stream.finish().await {
+                        warn!(&logger, "Failed to finish HTTP/3 stream: {:?}", e);
+                    }
+                });
+            }
+        });
+    }
+
+    Ok(())
+}
+
 #[fbinit::main]
 fn main(fb: FacebookInit) -> Result<(), Error> {
     let app = args::MononokeApp {
@@ -103,6 +411,48 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
             .long("--tls-ticket-seeds")
             .takes_value(true),
     )
+    .arg(
+        Arg::with_name(ARG_TLS_BACKEND)
+            .long("--tls-backend")
+            .takes_value(true)
+            .possible_values(&[TLS_BACKEND_OPENSSL, TLS_BACKEND_RUSTLS])
+            .default_value(TLS_BACKEND_OPENSSL)
+            .help("Which TLS implementation to use for the listening socket"),
+    )
+    .arg(
+        Arg::with_name(ARG_REQUIRE_CLIENT_CERT)
+            .long("--require-client-cert")
+            .takes_value(false)
+            .help("Require and verify a client certificate against --tls-ca, and authenticate the caller from it"),
+    )
+    .arg(
+        Arg::with_name(ARG_LISTEN_QUIC_PORT)
+            .long("--listen-quic-port")
+            .takes_value(true)
+            .help(
+                "If set, also serve HTTP/3 over QUIC on this port, reusing --tls-certificate/--tls-private-key",
+            ),
+    )
+    .arg(
+        Arg::with_name(ARG_UPSTREAM_TLS_ROOTS)
+            .long("--upstream-tls-roots")
+            .takes_value(true)
+            .default_value("system")
+            .help(
+                "How to verify TLS for --upstream-url: 'system' (OS trust store), 'bundled' \
+                 (compiled-in Mozilla roots), or 'ca-file=<path>' (an explicit CA bundle)",
+            ),
+    )
+    .arg(
+        Arg::with_name(ARG_LISTEN_UNIX)
+            .long("--listen-unix")
+            .takes_value(true)
+            .conflicts_with(ARG_TLS_CERTIFICATE)
+            .help(
+                "Listen on this UNIX domain socket path instead of TCP, e.g. to run as a \
+                 co-located sidecar behind a local reverse proxy. Mutually exclusive with TLS",
+            ),
+    )
     .arg(
         Arg::with_name(ARG_SELF_URL)
             .takes_value(true)
@@ -142,6 +492,10 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
     let tls_private_key = matches.value_of(ARG_TLS_PRIVATE_KEY);
     let tls_ca = matches.value_of(ARG_TLS_CA);
     let tls_ticket_seeds = matches.value_of(ARG_TLS_TICKET_SEEDS);
+    let tls_backend = matches.value_of(ARG_TLS_BACKEND).unwrap_or(TLS_BACKEND_OPENSSL);
+    let require_client_cert = matches.is_present(ARG_REQUIRE_CLIENT_CERT);
+    let listen_quic_port = matches.value_of(ARG_LISTEN_QUIC_PORT);
+    let listen_unix = matches.value_of(ARG_LISTEN_UNIX);
 
     let mut scuba_logger = if let Some(scuba_dataset) = matches.value_of(ARG_SCUBA_DATASET) {
         ScubaSampleBuilder::new(fb, scuba_dataset)
@@ -151,6 +505,10 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
 
     scuba_logger.add_common_server_data();
 
+    let upstream_tls_roots = parse_upstream_tls_roots(
+        matches.value_of(ARG_UPSTREAM_TLS_ROOTS).unwrap_or("system"),
+    )?;
+
     let server = ServerUris::new(
         matches.value_of(ARG_SELF_URL).unwrap(),
         matches.value_of(ARG_UPSTREAM_URL),
@@ -197,6 +555,7 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
         repos,
         server,
         matches.is_present(ARG_ALWAYS_WAIT_FOR_UPSTREAM),
+        upstream_tls_roots,
     )?;
 
     let router = build_router(ctx);
@@ -211,6 +570,49 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
         .add(TimerMiddleware::new())
         .build(router);
 
+    // Shared so the QUIC listener below and the TCP/UNIX listener further down dispatch into the
+    // very same router and middleware stack instead of each building their own.
+    let root = Arc::new(root);
+
+    if let Some(listen_quic_port) = listen_quic_port {
+        let (quic_tls_certificate, quic_tls_private_key) = match (tls_certificate, tls_private_key)
+        {
+            (Some(cert), Some(key)) => (cert, key),
+            _ => {
+                return Err(err_msg(
+                    "--listen-quic-port requires --tls-certificate and --tls-private-key",
+                ))
+            }
+        };
+
+        let quic_fut = serve_quic(
+            listen_host,
+            listen_quic_port,
+            quic_tls_certificate,
+            quic_tls_private_key,
+            root.clone(),
+            logger.clone(),
+        )
+        .boxed()
+        .compat();
+
+        runtime.spawn(quic_fut.map_err({
+            let logger = logger.clone();
+            move |e| warn!(&logger, "QUIC server failed: {:?}", e)
+        }));
+    }
+
+    if let Some(listen_unix) = listen_unix {
+        let listener = tokio::net::UnixListener::bind(listen_unix)
+            .chain_err(err_msg("Could not start UNIX listener"))?;
+
+        let server = bind_server(listener, root, |socket| Ok(socket).into_future());
+
+        return runtime
+            .block_on(server)
+            .map_err(|()| err_msg("Server failed"));
+    }
+
     let addr = format!("{}:{}", listen_host, listen_port);
 
     let addr = addr
@@ -221,7 +623,61 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
 
     let listener = TcpListener::bind(&addr).chain_err(err_msg("Could not start TCP listener"))?;
 
+    if require_client_cert && tls_certificate.is_none() {
+        return Err(err_msg(
+            "--require-client-cert requires --tls-certificate, --tls-private-key and --tls-ca",
+        ));
+    }
+
     match (tls_certificate, tls_private_key, tls_ca, tls_ticket_seeds) {
+        (Some(tls_certificate), Some(tls_private_key), Some(tls_ca), _)
+            if tls_backend == TLS_BACKEND_RUSTLS =>
+        {
+            let client_ca = if require_client_cert {
+                Some(tls_ca)
+            } else {
+                None
+            };
+            let config = build_rustls_server_config(tls_certificate, tls_private_key, client_ca)?;
+            let acceptor = TlsAcceptor::from(Arc::new(config));
+
+            let server = bind_server(listener, root, move |socket| {
+                let logger = logger.clone();
+                acceptor
+                    .accept(socket)
+                    .map_err({
+                        let logger = logger.clone();
+                        move |e| {
+                            warn!(&logger, "TLS handshake failed: {:?}", e);
+                            ()
+                        }
+                    })
+                    .map(move |stream| {
+                        // With `--require-client-cert`, the handshake above already rejected
+                        // connections that failed to present a cert verified against `--tls-ca`.
+                        // Attach the authenticated identity to the stream as `IdentifiedStream`
+                        // does; reading it back out into Gotham state is a `ClientIdentityMiddleware`
+                        // change this commit doesn't include.
+                        let mut identity = None;
+                        if require_client_cert {
+                            if let Some(certs) = stream.get_ref().1.get_peer_certificates() {
+                                match extract_client_identity(&certs) {
+                                    Ok(id) => {
+                                        slog::debug!(&logger, "Authenticated client: {}", id);
+                                        identity = Some(id);
+                                    }
+                                    Err(e) => warn!(&logger, "Could not extract client identity: {:?}", e),
+                                }
+                            }
+                        }
+                        IdentifiedStream::new(stream, identity)
+                    })
+            });
+
+            runtime
+                .block_on(server)
+                .map_err(|()| err_msg("Server failed"))?;
+        }
         (Some(tls_certificate), Some(tls_private_key), Some(tls_ca), tls_ticket_seeds) => {
             let config = secure_utils::SslConfig {
                 cert: tls_certificate.to_string(),
@@ -233,7 +689,11 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
                 .unwrap_or(secure_utils::fb_tls::SEED_PATH)
                 .to_string();
 
-            let tls_builder = secure_utils::build_tls_acceptor_builder(config.clone())?;
+            let mut tls_builder = secure_utils::build_tls_acceptor_builder(config.clone())?;
+            if require_client_cert {
+                tls_builder
+                    .set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+            }
             let fbs_tls_builder = secure_utils::fb_tls::tls_acceptor_builder(
                 logger.clone(),
                 config.clone(),
@@ -243,13 +703,33 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
             let acceptor = fbs_tls_builder.build();
 
             let server = bind_server(listener, root, move |socket| {
-                acceptor.accept_async(socket).map_err({
-                    let logger = logger.clone();
-                    move |e| {
-                        warn!(&logger, "TLS handshake failed: {:?}", e);
-                        ()
-                    }
-                })
+                let logger = logger.clone();
+                acceptor
+                    .accept_async(socket)
+                    .map_err({
+                        let logger = logger.clone();
+                        move |e| {
+                            warn!(&logger, "TLS handshake failed: {:?}", e);
+                            ()
+                        }
+                    })
+                    .map(move |stream| {
+                        // See the rustls branch above: the handshake already enforced
+                        // `--require-client-cert`; attach the identity the same way.
+                        let mut identity = None;
+                        if require_client_cert {
+                            if let Some(cert) = stream.get_ref().ssl().peer_certificate() {
+                                match extract_client_identity_openssl(&cert) {
+                                    Ok(id) => {
+                                        slog::debug!(&logger, "Authenticated client: {}", id);
+                                        identity = Some(id);
+                                    }
+                                    Err(e) => warn!(&logger, "Could not extract client identity: {:?}", e),
+                                }
+                            }
+                        }
+                        IdentifiedStream::new(stream, identity)
+                    })
             });
 
             runtime